@@ -0,0 +1,506 @@
+// The action map sits between the model and the Gestures. Each label the model predicts can be mapped to a pose,
+// or a sequence of poses, and either holds it while the label is predicted or toggles it each time the label
+// starts being predicted. devil-embedded keeps the map in its ConfigStore, and it can be changed over serial
+// while the arm runs.
+//
+// Nothing in here touches the hardware, the Gestures only need something that implements Joint (see servo.rs),
+// so the mapping can be run against a mock servo off the chip.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// The angle of each servo, in degrees
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pose {
+    pub thumb: u16,
+    pub fingers: u16,
+    pub arm: u16,
+}
+
+impl Pose {
+    pub const fn new(thumb: u16, fingers: u16, arm: u16) -> Self {
+        Self {
+            thumb,
+            fingers,
+            arm,
+        }
+    }
+
+    /// The pose a fraction t of the way from self to other
+    pub fn lerp(&self, other: &Pose, t: f32) -> Pose {
+        let lerp = |from: u16, to: u16| {
+            (from as f32 + (to as f32 - from as f32) * t.clamp(0.0, 1.0) + 0.5) as u16
+        };
+        Pose::new(
+            lerp(self.thumb, other.thumb),
+            lerp(self.fingers, other.fingers),
+            lerp(self.arm, other.arm),
+        )
+    }
+}
+
+// Parse `thumb,fingers,arm`, which is how poses and delays are written
+fn parse_joints<T: FromStr>(s: &str) -> Option<(T, T, T)> {
+    let mut values = s.split(',').map(|value| value.trim().parse::<T>());
+    match (values.next(), values.next(), values.next(), values.next()) {
+        (Some(Ok(thumb)), Some(Ok(fingers)), Some(Ok(arm)), None) => Some((thumb, fingers, arm)),
+        _ => None,
+    }
+}
+
+impl FromStr for Pose {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (thumb, fingers, arm) = parse_joints(s).ok_or(())?;
+        Ok(Self::new(thumb, fingers, arm))
+    }
+}
+
+/// How long each servo waits after a keyframe starts before it moves there, in milliseconds. Lets a joint get
+/// out of the way before another one moves, like the thumb before the fingers close.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delays {
+    pub thumb: u32,
+    pub fingers: u32,
+    pub arm: u32,
+}
+
+impl FromStr for Delays {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (thumb, fingers, arm) = parse_joints(s).ok_or(())?;
+        Ok(Self {
+            thumb,
+            fingers,
+            arm,
+        })
+    }
+}
+
+/// A pose in a sequence, along with how to get there and how long to stay there before moving on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub pose: Pose,
+    /// How long each servo takes to move into the pose, so they arrive together. None moves them at the pace of
+    /// the motion config.
+    pub move_ms: Option<u32>,
+    pub delays: Delays,
+    /// Counted from when the last servo has finished its move
+    pub hold_ms: u32,
+}
+
+/// What the arm does for a label
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Motion {
+    /// Go straight to a pose
+    Pose(Pose),
+    /// Go through the keyframes in order, ending on the last one
+    Sequence(Vec<Keyframe>),
+    /// Close from open towards closed as hard as the wearer contracts, see grip.rs
+    Grip { open: Pose, closed: Pose },
+}
+
+/// When the motion of a label is done
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Trigger {
+    /// Do the motion while the label is predicted, and go back to rest once it isn't
+    Hold,
+    /// Every time the label starts being predicted, switch between the motion and rest
+    Toggle,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Action {
+    pub label: String,
+    pub motion: Motion,
+    pub trigger: Trigger,
+}
+
+/// The action of every label, labels without an action leave the arm where it is
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionMap {
+    /// The pose the arm goes back to when nothing is held
+    pub rest: Pose,
+    pub actions: Vec<Action>,
+}
+
+impl Default for ActionMap {
+    // The thumbs up and pinch poses from Gestures
+    fn default() -> Self {
+        Self {
+            rest: Pose::new(0, 0, 0),
+            actions: vec![
+                Action {
+                    label: String::from("flex"),
+                    motion: Motion::Pose(Pose::new(0, 180, 180)),
+                    trigger: Trigger::Hold,
+                },
+                Action {
+                    label: String::from("fist"),
+                    motion: Motion::Pose(Pose::new(90, 90, 180)),
+                    trigger: Trigger::Hold,
+                },
+            ],
+        }
+    }
+}
+
+impl ActionMap {
+    /// The motion of a label, rest is the rest pose unless an action is called that
+    pub fn motion(&self, label: &str) -> Option<Motion> {
+        match self.find(label) {
+            Some(index) => Some(self.actions[index].motion.clone()),
+            None if label == "rest" => Some(Motion::Pose(self.rest)),
+            None => None,
+        }
+    }
+
+    /// The index of the action of a label
+    pub fn find(&self, label: &str) -> Option<usize> {
+        self.actions.iter().position(|action| action.label == label)
+    }
+}
+
+/// Turns the stream of predicted labels into motions, following the trigger of each action
+pub struct ActionMapper {
+    map: ActionMap,
+    // The action the arm is currently doing, None when at rest
+    active: Option<usize>,
+    // The label of the last update
+    previous: Option<String>,
+}
+
+impl ActionMapper {
+    pub fn new(map: ActionMap) -> Self {
+        Self {
+            map,
+            active: None,
+            previous: None,
+        }
+    }
+
+    /// Swap in a new map, the arm is treated as being at rest afterwards
+    pub fn set_map(&mut self, map: ActionMap) {
+        self.map = map;
+        self.reset();
+    }
+
+    /// Forget the labels seen so far, the arm is treated as being at rest afterwards
+    pub fn reset(&mut self) {
+        self.active = None;
+        self.previous = None;
+    }
+
+    /// The pose the arm goes back to when nothing is held
+    pub fn rest(&self) -> Pose {
+        self.map.rest
+    }
+
+    /// Feed in the latest predicted label, returns the motion to do if anything has to change. A label which
+    /// isn't in the map (like the rejection class) releases whatever is being held.
+    pub fn update(&mut self, label: &str) -> Option<Motion> {
+        if self.previous.as_deref() == Some(label) {
+            return None;
+        }
+        self.previous = Some(String::from(label));
+
+        let rest = Motion::Pose(self.map.rest);
+        match self.map.find(label) {
+            Some(index) => {
+                let action = &self.map.actions[index];
+                if action.trigger == Trigger::Toggle && self.active == Some(index) {
+                    self.active = None;
+                    Some(rest)
+                } else {
+                    self.active = Some(index);
+                    Some(action.motion.clone())
+                }
+            }
+            None => match self.active {
+                Some(active) if self.map.actions[active].trigger == Trigger::Hold => {
+                    self.active = None;
+                    Some(rest)
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+// Parse a keyframe, written as `thumb,fingers,arm[~move_ms][>thumb,fingers,arm delays][@hold_ms]`
+fn parse_keyframe(keyframe: &str) -> Option<Keyframe> {
+    let (keyframe, hold_ms) = keyframe.split_once('@').unwrap_or((keyframe, "0"));
+    let (keyframe, delays) = match keyframe.split_once('>') {
+        Some((keyframe, delays)) => (keyframe, delays.parse().ok()?),
+        None => (keyframe, Delays::default()),
+    };
+    let (pose, move_ms) = match keyframe.split_once('~') {
+        Some((pose, move_ms)) => (pose, Some(move_ms.parse().ok()?)),
+        None => (keyframe, None),
+    };
+
+    Some(Keyframe {
+        pose: pose.parse().ok()?,
+        move_ms,
+        delays,
+        hold_ms: hold_ms.parse().ok()?,
+    })
+}
+
+/// Parse keyframes separated by spaces, a single plain pose is a Motion::Pose. A grip is `grip <open> <closed>`.
+pub fn parse_motion<'a>(mut poses: impl Iterator<Item = &'a str>) -> Option<Motion> {
+    let first = poses.next()?;
+    if first == "grip" {
+        let open = poses.next()?.parse().ok()?;
+        let closed = poses.next()?.parse().ok()?;
+        return poses
+            .next()
+            .is_none()
+            .then_some(Motion::Grip { open, closed });
+    }
+
+    let rest: Vec<&str> = poses.collect();
+    if rest.is_empty() && !first.contains(['@', '>', '~']) {
+        return Some(Motion::Pose(first.parse().ok()?));
+    }
+
+    core::iter::once(first)
+        .chain(rest)
+        .map(parse_keyframe)
+        .collect::<Option<Vec<_>>>()
+        .map(Motion::Sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::servo::Joint;
+
+    // Remembers the angle it was last turned to
+    #[derive(Default)]
+    struct MockJoint {
+        degree: f32,
+    }
+
+    impl Joint for MockJoint {
+        fn start(&mut self) {}
+        fn stop(&mut self) {}
+
+        fn rotate(&mut self, degree: f32) {
+            self.degree = degree;
+        }
+
+        fn max_degree_rotation(&self) -> f32 {
+            180.0
+        }
+    }
+
+    // Three mock joints which are moved to where each motion ends up, the way Gestures plays them
+    #[derive(Default)]
+    struct MockArm {
+        thumb: MockJoint,
+        fingers: MockJoint,
+        arm: MockJoint,
+    }
+
+    impl MockArm {
+        fn play(&mut self, motion: &Motion) {
+            let pose = match motion {
+                Motion::Pose(pose) => *pose,
+                Motion::Grip { open, .. } => *open,
+                Motion::Sequence(keyframes) => keyframes.last().unwrap().pose,
+            };
+            self.thumb.rotate(pose.thumb.into());
+            self.fingers.rotate(pose.fingers.into());
+            self.arm.rotate(pose.arm.into());
+        }
+
+        fn pose(&self) -> Pose {
+            Pose::new(
+                self.thumb.degree as u16,
+                self.fingers.degree as u16,
+                self.arm.degree as u16,
+            )
+        }
+    }
+
+    const REST: Pose = Pose::new(10, 10, 10);
+    const FIST: Pose = Pose::new(90, 90, 180);
+    const POINT: Pose = Pose::new(0, 180, 180);
+
+    fn map() -> ActionMap {
+        ActionMap {
+            rest: REST,
+            actions: vec![
+                Action {
+                    label: String::from("fist"),
+                    motion: Motion::Pose(FIST),
+                    trigger: Trigger::Hold,
+                },
+                Action {
+                    label: String::from("point"),
+                    motion: Motion::Pose(POINT),
+                    trigger: Trigger::Toggle,
+                },
+            ],
+        }
+    }
+
+    // Feed labels through a mapper into a mock arm, returning the pose after each one
+    fn run(labels: &[&str]) -> Vec<Pose> {
+        let mut mapper = ActionMapper::new(map());
+        let mut arm = MockArm::default();
+        arm.play(&Motion::Pose(mapper.rest()));
+        labels
+            .iter()
+            .map(|label| {
+                if let Some(motion) = mapper.update(label) {
+                    arm.play(&motion);
+                }
+                arm.pose()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hold_is_released_on_an_unknown_label() {
+        assert_eq!(
+            run(&["fist", "fist", "unknown", "nothing"]),
+            vec![FIST, FIST, REST, REST]
+        );
+    }
+
+    #[test]
+    fn toggle_switches_on_and_off_each_time_the_label_starts() {
+        assert_eq!(
+            run(&["point", "point", "unknown", "point", "unknown", "point"]),
+            vec![POINT, POINT, POINT, REST, REST, POINT]
+        );
+    }
+
+    #[test]
+    fn another_action_takes_over_from_a_toggle() {
+        assert_eq!(run(&["point", "fist", "unknown"]), vec![POINT, FIST, REST]);
+    }
+
+    #[test]
+    fn repeated_labels_change_nothing() {
+        let mut mapper = ActionMapper::new(map());
+        assert_eq!(mapper.update("fist"), Some(Motion::Pose(FIST)));
+        assert_eq!(mapper.update("fist"), None);
+        assert_eq!(mapper.update("unknown"), Some(Motion::Pose(REST)));
+        assert_eq!(mapper.update("unknown"), None);
+    }
+
+    #[test]
+    fn unknown_label_at_rest_changes_nothing() {
+        let mut mapper = ActionMapper::new(map());
+        assert_eq!(mapper.update("unknown"), None);
+    }
+
+    #[test]
+    fn set_map_starts_from_rest() {
+        let mut mapper = ActionMapper::new(map());
+        mapper.update("point");
+        mapper.set_map(map());
+        assert_eq!(mapper.update("point"), Some(Motion::Pose(POINT)));
+    }
+
+    #[test]
+    fn motion_of_rest_is_the_rest_pose() {
+        assert_eq!(map().motion("rest"), Some(Motion::Pose(REST)));
+        assert_eq!(map().motion("fist"), Some(Motion::Pose(FIST)));
+        assert_eq!(map().motion("wave"), None);
+    }
+
+    fn parse(motion: &str) -> Option<Motion> {
+        parse_motion(motion.split_whitespace())
+    }
+
+    #[test]
+    fn parse_plain_pose() {
+        assert_eq!(parse("90,90,180"), Some(Motion::Pose(FIST)));
+    }
+
+    #[test]
+    fn parse_needs_exactly_three_joints() {
+        assert_eq!(parse("90,90"), None);
+        assert_eq!(parse("90,90,180,0"), None);
+        assert_eq!(parse("90,90~500"), None);
+        assert_eq!(parse("90,90,180>0,100"), None);
+        assert_eq!(parse("90,90,180>0,100,0,0"), None);
+    }
+
+    #[test]
+    fn parse_rejects_bad_numbers() {
+        assert_eq!(parse("90,x,180"), None);
+        assert_eq!(parse("90,90,180~"), None);
+        assert_eq!(parse("90,90,180@soon"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn parse_keyframe_parts() {
+        let keyframe = |pose, move_ms, delays, hold_ms| Keyframe {
+            pose,
+            move_ms,
+            delays,
+            hold_ms,
+        };
+        let delays = Delays {
+            thumb: 0,
+            fingers: 250,
+            arm: 0,
+        };
+
+        assert_eq!(
+            parse("90,90,180~500"),
+            Some(Motion::Sequence(vec![keyframe(
+                FIST,
+                Some(500),
+                Delays::default(),
+                0
+            )]))
+        );
+        assert_eq!(
+            parse("90,90,180>0,250,0"),
+            Some(Motion::Sequence(vec![keyframe(FIST, None, delays, 0)]))
+        );
+        assert_eq!(
+            parse("90,90,180@1000"),
+            Some(Motion::Sequence(vec![keyframe(
+                FIST,
+                None,
+                Delays::default(),
+                1000
+            )]))
+        );
+        assert_eq!(
+            parse("90,90,180~500>0,250,0@1000 0,180,180"),
+            Some(Motion::Sequence(vec![
+                keyframe(FIST, Some(500), delays, 1000),
+                keyframe(POINT, None, Delays::default(), 0),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parse_grip() {
+        assert_eq!(
+            parse("grip 10,10,10 90,90,180"),
+            Some(Motion::Grip {
+                open: REST,
+                closed: FIST
+            })
+        );
+        assert_eq!(parse("grip 10,10,10"), None);
+        assert_eq!(parse("grip 10,10 90,90,180"), None);
+        assert_eq!(parse("grip 10,10,10 90,90,180 0,0,0"), None);
+    }
+}
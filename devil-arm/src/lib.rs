@@ -0,0 +1,13 @@
+// No Standard library is required to run this code, this is so that it can run on the micro controller.
+#![no_std]
+
+// The parts of devil-embedded which don't need the RP2040 itself, only traits like embedded-hal's. Keeping them
+// out of the firmware crate means they build on a normal computer, so they can be tested there against mocks.
+
+extern crate alloc;
+
+pub mod action;
+pub mod motion;
+pub mod sensor;
+pub mod servo;
+pub mod storage;
@@ -0,0 +1,162 @@
+// How long a servo takes to move. devil-embedded's MotionPlanner eases each servo to its target along a Curve,
+// and this decides how long that takes, so the speed of a move is known (and can be tested) off the chip.
+
+use serde::{Deserialize, Serialize};
+
+/// The longest a single move may be configured to take, in milliseconds
+pub const MAX_MOVE_MS: u32 = 10_000;
+
+/// The shape of a move, each one eases in and out
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Curve {
+    Linear,
+    Quad,
+    Cubic,
+    Sine,
+}
+
+impl Curve {
+    /// How many times faster than on average the move goes at its fastest, half way through
+    pub fn peak_slope(self) -> f32 {
+        match self {
+            Self::Linear => 1.0,
+            Self::Quad => 2.0,
+            Self::Cubic => 3.0,
+            Self::Sine => core::f32::consts::FRAC_PI_2,
+        }
+    }
+}
+
+/// How long a move takes
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Timing {
+    /// Take this many milliseconds, however far the servo has to go
+    Duration(u32),
+    /// Never go faster than this many degrees per second, the move is stretched so the fastest part of its
+    /// curve is at this speed
+    MaxSpeed(f32),
+}
+
+impl Timing {
+    /// How long a move of distance degrees along curve takes, in seconds. A speed which isn't positive moves
+    /// straight there.
+    pub fn duration(self, distance: f32, curve: Curve) -> f32 {
+        match self {
+            Self::Duration(ms) => ms as f32 / 1000.0,
+            Self::MaxSpeed(speed) if speed > 0.0 => distance.abs() / speed * curve.peak_slope(),
+            Self::MaxSpeed(_) => 0.0,
+        }
+    }
+}
+
+/// The timing and curve used for every move made through Joint::rotate
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MotionConfig {
+    pub timing: Timing,
+    pub curve: Curve,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            timing: Timing::MaxSpeed(360.0),
+            curve: Curve::Sine,
+        }
+    }
+}
+
+impl MotionConfig {
+    /// Whether every move finishes, in no more than MAX_MOVE_MS when it is timed
+    pub fn is_valid(&self) -> bool {
+        match self.timing {
+            Timing::Duration(ms) => ms <= MAX_MOVE_MS,
+            Timing::MaxSpeed(speed) => speed.is_finite() && speed > 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn a_timed_move_takes_its_duration_however_far_it_goes() {
+        for curve in [Curve::Linear, Curve::Sine] {
+            assert_eq!(Timing::Duration(250).duration(10.0, curve), 0.25);
+            assert_eq!(Timing::Duration(250).duration(-170.0, curve), 0.25);
+        }
+    }
+
+    #[test]
+    fn max_speed_is_stretched_by_the_peak_slope_of_the_curve() {
+        let speed = Timing::MaxSpeed(90.0);
+        assert_eq!(speed.duration(90.0, Curve::Linear), 1.0);
+        assert_eq!(speed.duration(90.0, Curve::Quad), 2.0);
+        assert_eq!(speed.duration(-90.0, Curve::Cubic), 3.0);
+        assert_eq!(
+            speed.duration(90.0, Curve::Sine),
+            core::f32::consts::FRAC_PI_2
+        );
+    }
+
+    #[test]
+    fn max_speed_is_never_exceeded() {
+        // The ease in and out of each curve, as a fraction of the way there at a fraction of the time
+        let ease = |curve: Curve, t: f32| match curve {
+            Curve::Linear => t,
+            Curve::Quad if t < 0.5 => 2.0 * t * t,
+            Curve::Quad => 1.0 - 2.0 * (1.0 - t) * (1.0 - t),
+            Curve::Cubic if t < 0.5 => 4.0 * t * t * t,
+            Curve::Cubic => 1.0 - 4.0 * (1.0 - t) * (1.0 - t) * (1.0 - t),
+            Curve::Sine => (1.0 - (core::f32::consts::PI * t).cos()) / 2.0,
+        };
+
+        let (speed, distance) = (120.0, 150.0);
+        for curve in [Curve::Linear, Curve::Quad, Curve::Cubic, Curve::Sine] {
+            let duration = Timing::MaxSpeed(speed).duration(distance, curve);
+            let steps = 1000;
+            let dt = duration / steps as f32;
+            let fastest = (0..steps)
+                .map(|step| {
+                    let (from, to) = (step as f32 / steps as f32, (step + 1) as f32 / steps as f32);
+                    distance * (ease(curve, to) - ease(curve, from)) / dt
+                })
+                .fold(0.0, f32::max);
+            assert!(
+                fastest <= speed * 1.001,
+                "{:?} reached {} degrees per second",
+                curve,
+                fastest
+            );
+            assert!(
+                fastest >= speed * 0.99,
+                "{:?} only reached {} degrees per second",
+                curve,
+                fastest
+            );
+        }
+    }
+
+    #[test]
+    fn speed_that_isnt_positive_moves_straight_there() {
+        for speed in [0.0, -10.0, f32::NAN] {
+            assert_eq!(Timing::MaxSpeed(speed).duration(90.0, Curve::Sine), 0.0);
+        }
+    }
+
+    #[test]
+    fn only_moves_that_finish_are_valid() {
+        let config = |timing| MotionConfig {
+            timing,
+            curve: Curve::Sine,
+        };
+        assert!(MotionConfig::default().is_valid());
+        assert!(config(Timing::Duration(MAX_MOVE_MS)).is_valid());
+        assert!(!config(Timing::Duration(MAX_MOVE_MS + 1)).is_valid());
+        for speed in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(!config(Timing::MaxSpeed(speed)).is_valid(), "{}", speed);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+// Driver for an external ADS1299 style 24-bit biopotential ADC over SPI. The ADC runs on its own clock and
+// pulls DRDY low whenever a new sample from every channel is ready, which paces the sensor task.
+// The driver only relies on the embedded-hal-async traits, so any SpiDevice and pin can be used, including the
+// mocks in the tests below.
+
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+use devil_dsp::{Sample, CHANNELS};
+
+use super::SensorSource;
+
+// Commands, see the "SPI Command Definitions" table of the ADS1299 datasheet
+const CMD_RESET: u8 = 0x06;
+const CMD_START: u8 = 0x08;
+const CMD_RDATAC: u8 = 0x10; // Read data continuously
+const CMD_SDATAC: u8 = 0x11; // Stop reading data continuously, required before touching registers
+const CMD_RREG: u8 = 0x20;
+const CMD_WREG: u8 = 0x40;
+
+// Registers
+const REG_ID: u8 = 0x00;
+const REG_CONFIG1: u8 = 0x01;
+const REG_CONFIG3: u8 = 0x03;
+const REG_CH1SET: u8 = 0x05;
+
+// The lower bits of the ID register of the ADS1299 family
+const DEVICE_ID: u8 = 0x1E;
+const DEVICE_ID_MASK: u8 = 0x1F;
+
+// 1 kSPS, this has to match devil_dsp::SAMPLE_RATE_HZ
+const CONFIG1_1KSPS: u8 = 0x94;
+// Use the internal reference
+const CONFIG3_INTERNAL_REFERENCE: u8 = 0xE0;
+// Gain of 24 with the normal electrode input
+const CHSET_GAIN_24: u8 = 0x60;
+// Power down the channel, and short its inputs
+const CHSET_POWER_DOWN: u8 = 0x81;
+
+// The number of channels on the device, unused ones are powered down
+pub const DEVICE_CHANNELS: usize = 8;
+
+const _: () = assert!(
+    CHANNELS <= DEVICE_CHANNELS,
+    "the ADS1299 has fewer channels than devil_dsp::CHANNELS"
+);
+
+// Each frame is a 3 byte status word, followed by 3 bytes for every channel
+const FRAME_LEN: usize = 3 + 3 * DEVICE_CHANNELS;
+
+#[derive(Debug)]
+pub enum Error<S, P> {
+    Spi(S),
+    Pin(P),
+    // The ID register didn't match an ADS1299, holds the ID that was read
+    UnknownDevice(u8),
+}
+
+pub struct Ads1299<SPI, DRDY> {
+    spi: SPI,
+    drdy: DRDY,
+}
+
+impl<SPI: SpiDevice, DRDY: Wait> Ads1299<SPI, DRDY> {
+    /// Reset the ADC, check that it is really an ADS1299, configure the first CHANNELS channels and start
+    /// converting continuously.
+    pub async fn new(spi: SPI, drdy: DRDY) -> Result<Self, Error<SPI::Error, DRDY::Error>> {
+        let mut this = Self { spi, drdy };
+
+        this.command(CMD_RESET).await?;
+        // The device needs 18 clock cycles after a reset, stopping continuous mode easily covers that
+        this.command(CMD_SDATAC).await?;
+
+        let id = this.read_register(REG_ID).await?;
+        if id & DEVICE_ID_MASK != DEVICE_ID {
+            return Err(Error::UnknownDevice(id));
+        }
+
+        this.write_register(REG_CONFIG1, CONFIG1_1KSPS).await?;
+        this.write_register(REG_CONFIG3, CONFIG3_INTERNAL_REFERENCE)
+            .await?;
+        for channel in 0..DEVICE_CHANNELS {
+            let setting = if channel < CHANNELS {
+                CHSET_GAIN_24
+            } else {
+                CHSET_POWER_DOWN
+            };
+            this.write_register(REG_CH1SET + channel as u8, setting)
+                .await?;
+        }
+
+        this.command(CMD_START).await?;
+        this.command(CMD_RDATAC).await?;
+
+        Ok(this)
+    }
+
+    async fn command(&mut self, command: u8) -> Result<(), Error<SPI::Error, DRDY::Error>> {
+        self.spi.write(&[command]).await.map_err(Error::Spi)
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, Error<SPI::Error, DRDY::Error>> {
+        let mut value = [0u8];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[CMD_RREG | register, 0x00]), // Read one register
+                Operation::Read(&mut value),
+            ])
+            .await
+            .map_err(Error::Spi)?;
+        Ok(value[0])
+    }
+
+    async fn write_register(
+        &mut self,
+        register: u8,
+        value: u8,
+    ) -> Result<(), Error<SPI::Error, DRDY::Error>> {
+        self.spi
+            .write(&[CMD_WREG | register, 0x00, value]) // Write one register
+            .await
+            .map_err(Error::Spi)
+    }
+}
+
+// Convert a signed 24-bit conversion into the same unsigned, mid scale centered range the on chip ADC uses,
+// by keeping the top 16 bits
+fn to_sample(bytes: &[u8]) -> u16 {
+    let raw = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8; // sign extend the 24 bits
+    ((raw + 0x80_0000) >> 8) as u16
+}
+
+impl<SPI: SpiDevice, DRDY: Wait> SensorSource for Ads1299<SPI, DRDY> {
+    type Error = Error<SPI::Error, DRDY::Error>;
+
+    async fn read(&mut self) -> Result<Sample, Self::Error> {
+        self.drdy.wait_for_low().await.map_err(Error::Pin)?;
+
+        let mut frame = [0u8; FRAME_LEN];
+        self.spi.read(&mut frame).await.map_err(Error::Spi)?;
+
+        // Skip the status word, then take 3 bytes for each channel in use
+        let mut sample: Sample = [0; CHANNELS];
+        for (value, bytes) in sample.iter_mut().zip(frame[3..].chunks_exact(3)) {
+            *value = to_sample(bytes);
+        }
+
+        Ok(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTransaction};
+    use embedded_hal_mock::eh1::spi::{Mock as SpiMock, Transaction};
+    use futures::executor::block_on;
+
+    use super::*;
+
+    // The transactions of a command or register write, which are a single write
+    fn write(bytes: &[u8]) -> Vec<Transaction<u8>> {
+        vec![
+            Transaction::transaction_start(),
+            Transaction::write_vec(bytes.to_vec()),
+            Transaction::transaction_end(),
+        ]
+    }
+
+    // Everything new does up to and including reading the ID register, which answers with id
+    fn reset_and_read_id(id: u8) -> Vec<Transaction<u8>> {
+        let mut transactions = write(&[CMD_RESET]);
+        transactions.extend(write(&[CMD_SDATAC]));
+        transactions.extend([
+            Transaction::transaction_start(),
+            Transaction::write_vec(vec![CMD_RREG | REG_ID, 0x00]),
+            Transaction::read_vec(vec![id]),
+            Transaction::transaction_end(),
+        ]);
+        transactions
+    }
+
+    fn setup() -> Vec<Transaction<u8>> {
+        // The upper bits of the ID are the revision, which doesn't matter
+        let mut transactions = reset_and_read_id(0xE0 | DEVICE_ID);
+        transactions.extend(write(&[CMD_WREG | REG_CONFIG1, 0x00, CONFIG1_1KSPS]));
+        transactions.extend(write(&[
+            CMD_WREG | REG_CONFIG3,
+            0x00,
+            CONFIG3_INTERNAL_REFERENCE,
+        ]));
+        for channel in 0..DEVICE_CHANNELS {
+            let setting = if channel < CHANNELS {
+                CHSET_GAIN_24
+            } else {
+                CHSET_POWER_DOWN
+            };
+            transactions.extend(write(&[
+                CMD_WREG | (REG_CH1SET + channel as u8),
+                0x00,
+                setting,
+            ]));
+        }
+        transactions.extend(write(&[CMD_START]));
+        transactions.extend(write(&[CMD_RDATAC]));
+        transactions
+    }
+
+    #[test]
+    fn new_configures_the_channels_and_starts_converting() {
+        let mut spi = SpiMock::new(&setup());
+        let mut drdy = PinMock::new(&[]);
+
+        let adc = block_on(Ads1299::new(spi.clone(), drdy.clone()));
+        assert!(adc.is_ok());
+
+        spi.done();
+        drdy.done();
+    }
+
+    #[test]
+    fn new_refuses_another_device() {
+        let mut spi = SpiMock::new(&reset_and_read_id(0x12));
+        let mut drdy = PinMock::new(&[]);
+
+        let adc = block_on(Ads1299::new(spi.clone(), drdy.clone()));
+        assert!(matches!(adc, Err(Error::UnknownDevice(0x12))));
+
+        spi.done();
+        drdy.done();
+    }
+
+    #[test]
+    fn read_waits_for_drdy_and_decodes_every_channel() {
+        // Zero, the largest and smallest conversions, and a step either side of zero
+        let conversions: [u32; DEVICE_CHANNELS] = [
+            0x000000, 0x7FFFFF, 0x800000, 0x000100, 0xFFFF00, 0x400000, 0xC00000, 0x000000,
+        ];
+        let expected: [u16; DEVICE_CHANNELS] = [
+            0x8000, 0xFFFF, 0x0000, 0x8001, 0x7FFF, 0xC000, 0x4000, 0x8000,
+        ];
+
+        let mut frame = vec![0xC0, 0x00, 0x00]; // The status word
+        for conversion in conversions {
+            frame.extend_from_slice(&conversion.to_be_bytes()[1..]);
+        }
+
+        let mut transactions = setup();
+        transactions.extend([
+            Transaction::transaction_start(),
+            Transaction::read_vec(frame),
+            Transaction::transaction_end(),
+        ]);
+        let mut spi = SpiMock::new(&transactions);
+        let mut drdy = PinMock::new(&[PinTransaction::wait_for_state(State::Low)]);
+
+        let mut adc = block_on(Ads1299::new(spi.clone(), drdy.clone())).unwrap();
+        let sample = block_on(adc.read()).unwrap();
+        assert_eq!(sample[..], expected[..CHANNELS]);
+
+        spi.done();
+        drdy.done();
+    }
+}
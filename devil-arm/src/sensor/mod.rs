@@ -0,0 +1,16 @@
+use devil_dsp::Sample;
+
+pub mod ads1299;
+
+pub use ads1299::Ads1299;
+
+/// Something the sensors can be read from. Each call to read waits until the next sample is due, so the source
+/// decides the sample rate, which has to be devil_dsp::SAMPLE_RATE_HZ.
+// The sensors are read on a single threaded executor, so the futures never have to be Send
+#[allow(async_fn_in_trait)]
+pub trait SensorSource {
+    type Error: core::fmt::Debug;
+
+    /// Wait for, then read a single sample from every sensor
+    async fn read(&mut self) -> Result<Sample, Self::Error>;
+}
@@ -0,0 +1,293 @@
+// What the rest of the arm needs from a servo. The servos themselves are driven by the PIO of the RP2040 in
+// devil-embedded, everything else only moves them through Joint. How an angle is turned into a pulse width is
+// kept here too, so the calibration can be tested off the chip.
+
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// The calibration of a single servo, see ServoLimits::pulse_width for how an angle is turned into a pulse
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServoLimits {
+    /// How far the servo turns between the min and max pulse widths, in degrees
+    pub max_degree_rotation: f32,
+    pub min_pulse_width_us: u64,
+    pub max_pulse_width_us: u64,
+    /// Turn the other way, so 0 degrees is the max pulse width
+    pub inverted: bool,
+    /// Added to every angle, to line the horn up with the joint after it has been fitted
+    pub neutral_offset: f32,
+    /// The angles the joint is kept between, however far the servo itself can turn
+    pub min_angle: f32,
+    pub max_angle: f32,
+}
+
+impl Default for ServoLimits {
+    // These values were detemined by a rough experiment with the MG996R servo
+    fn default() -> Self {
+        Self {
+            max_degree_rotation: 180.0,
+            min_pulse_width_us: 500,
+            max_pulse_width_us: 2500,
+            inverted: false,
+            neutral_offset: 0.0,
+            min_angle: 0.0,
+            max_angle: 180.0,
+        }
+    }
+}
+
+impl ServoLimits {
+    /// Whether the angles are all finite, the servo turns some way, and the soft limits are the right way round
+    pub fn is_valid(&self) -> bool {
+        self.max_degree_rotation.is_finite()
+            && self.max_degree_rotation > 0.0
+            && self.neutral_offset.is_finite()
+            && self.min_angle.is_finite()
+            && self.max_angle.is_finite()
+            && self.min_angle <= self.max_angle
+    }
+
+    /// The pulse width which turns the servo to degree. The angle is kept within the soft limits, offset by the
+    /// neutral offset, and then mapped onto the pulse widths, the other way round if the servo is inverted. It
+    /// never goes outside of the pulse widths, whichever order they are in. A NaN anywhere is ignored rather than
+    /// panicking, so an invalid calibration still gives a pulse width within the limits.
+    pub fn pulse_width(&self, degree: f32) -> Duration {
+        // f32::max and min return the other side when one is NaN, where clamp would panic on a NaN limit
+        let degree = degree
+            .max(self.min_angle)
+            .min(self.max_angle.max(self.min_angle))
+            + self.neutral_offset;
+        let mut position = if self.max_degree_rotation > 0.0 {
+            (degree / self.max_degree_rotation).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        if self.inverted {
+            position = 1.0 - position;
+        }
+
+        let shortest = self.min_pulse_width_us.min(self.max_pulse_width_us) * 1000;
+        let longest = self.min_pulse_width_us.max(self.max_pulse_width_us) * 1000;
+        let nanos = shortest + ((longest - shortest) as f32 * position + 0.5) as u64;
+        Duration::from_nanos(nanos.min(longest))
+    }
+}
+
+/// Something that can be turned to an angle. Gestures only moves its servos through this, so that it can be
+/// run against a mock servo off the chip.
+pub trait Joint {
+    fn start(&mut self);
+    fn stop(&mut self);
+    fn rotate(&mut self, degree: f32);
+    fn max_degree_rotation(&self) -> f32;
+
+    /// Move to degree over duration_ms. A joint which can't time its moves just goes straight there.
+    fn rotate_over(&mut self, degree: f32, duration_ms: u32) {
+        let _ = duration_ms;
+        self.rotate(degree);
+    }
+
+    /// How long rotate(degree) would take to get there, in milliseconds. A joint which goes straight there takes
+    /// no time.
+    fn move_ms(&self, degree: f32) -> u32 {
+        let _ = degree;
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1000us to 2000us over 100 degrees, so every degree is 10us
+    fn limits() -> ServoLimits {
+        ServoLimits {
+            max_degree_rotation: 100.0,
+            min_pulse_width_us: 1000,
+            max_pulse_width_us: 2000,
+            inverted: false,
+            neutral_offset: 0.0,
+            min_angle: 0.0,
+            max_angle: 100.0,
+        }
+    }
+
+    fn us(micros: u64) -> Duration {
+        Duration::from_micros(micros)
+    }
+
+    #[test]
+    fn maps_the_angle_onto_the_pulse_widths() {
+        let limits = limits();
+        assert_eq!(limits.pulse_width(0.0), us(1000));
+        assert_eq!(limits.pulse_width(25.0), us(1250));
+        assert_eq!(limits.pulse_width(100.0), us(2000));
+    }
+
+    #[test]
+    fn inverted_turns_the_other_way() {
+        let limits = ServoLimits {
+            inverted: true,
+            ..limits()
+        };
+        assert_eq!(limits.pulse_width(0.0), us(2000));
+        assert_eq!(limits.pulse_width(25.0), us(1750));
+        assert_eq!(limits.pulse_width(100.0), us(1000));
+    }
+
+    #[test]
+    fn pulse_widths_can_be_in_either_order() {
+        let swapped = ServoLimits {
+            min_pulse_width_us: 2000,
+            max_pulse_width_us: 1000,
+            ..limits()
+        };
+        for degree in [-50.0, 0.0, 25.0, 100.0, 150.0] {
+            assert_eq!(swapped.pulse_width(degree), limits().pulse_width(degree));
+        }
+    }
+
+    #[test]
+    fn neutral_offset_is_added_to_the_angle() {
+        let limits = ServoLimits {
+            neutral_offset: 10.0,
+            ..limits()
+        };
+        assert_eq!(limits.pulse_width(0.0), us(1100));
+        assert_eq!(limits.pulse_width(25.0), us(1350));
+        // The offset can't push the servo past its last pulse width
+        assert_eq!(limits.pulse_width(100.0), us(2000));
+    }
+
+    #[test]
+    fn angle_is_kept_within_the_soft_limits() {
+        let limits = ServoLimits {
+            min_angle: 20.0,
+            max_angle: 80.0,
+            ..limits()
+        };
+        assert_eq!(limits.pulse_width(0.0), us(1200));
+        assert_eq!(limits.pulse_width(50.0), us(1500));
+        assert_eq!(limits.pulse_width(100.0), us(1800));
+    }
+
+    #[test]
+    fn soft_limits_the_wrong_way_round_hold_the_min_angle() {
+        let limits = ServoLimits {
+            min_angle: 60.0,
+            max_angle: 40.0,
+            ..limits()
+        };
+        assert_eq!(limits.pulse_width(0.0), us(1600));
+        assert_eq!(limits.pulse_width(100.0), us(1600));
+    }
+
+    #[test]
+    fn clamps_at_both_ends() {
+        // Soft limits wider than the servo can turn, so only the pulse widths hold it
+        let limits = ServoLimits {
+            min_angle: -100.0,
+            max_angle: 200.0,
+            ..limits()
+        };
+        assert_eq!(limits.pulse_width(-100.0), us(1000));
+        assert_eq!(limits.pulse_width(200.0), us(2000));
+        assert_eq!(limits.pulse_width(f32::NEG_INFINITY), us(1000));
+        assert_eq!(limits.pulse_width(f32::INFINITY), us(2000));
+
+        let inverted = ServoLimits {
+            inverted: true,
+            ..limits
+        };
+        assert_eq!(inverted.pulse_width(-100.0), us(2000));
+        assert_eq!(inverted.pulse_width(200.0), us(1000));
+    }
+
+    #[test]
+    fn no_rotation_stays_at_the_first_pulse_width() {
+        let limits = ServoLimits {
+            max_degree_rotation: 0.0,
+            ..limits()
+        };
+        assert_eq!(limits.pulse_width(50.0), us(1000));
+    }
+
+    #[test]
+    fn default_is_not_inverted() {
+        let limits = ServoLimits::default();
+        assert!(limits.is_valid());
+        assert_eq!(limits.pulse_width(0.0), us(500));
+        assert_eq!(limits.pulse_width(180.0), us(2500));
+    }
+
+    #[test]
+    fn a_nan_never_panics_or_leaves_the_pulse_widths() {
+        let nan_limits = [
+            ServoLimits {
+                min_angle: f32::NAN,
+                ..limits()
+            },
+            ServoLimits {
+                max_angle: f32::NAN,
+                ..limits()
+            },
+            ServoLimits {
+                neutral_offset: f32::NAN,
+                ..limits()
+            },
+            ServoLimits {
+                max_degree_rotation: f32::NAN,
+                ..limits()
+            },
+        ];
+        for limits in nan_limits {
+            for degree in [f32::NAN, 0.0, 50.0, 100.0] {
+                let pulse = limits.pulse_width(degree);
+                assert!(
+                    pulse >= us(1000) && pulse <= us(2000),
+                    "{:?} at {} gave {:?}",
+                    limits,
+                    degree,
+                    pulse
+                );
+            }
+        }
+        assert_eq!(limits().pulse_width(f32::NAN), us(1000));
+    }
+
+    #[test]
+    fn only_finite_limits_the_right_way_round_are_valid() {
+        assert!(limits().is_valid());
+        let invalid = [
+            ServoLimits {
+                max_degree_rotation: 0.0,
+                ..limits()
+            },
+            ServoLimits {
+                max_degree_rotation: f32::INFINITY,
+                ..limits()
+            },
+            ServoLimits {
+                neutral_offset: f32::NAN,
+                ..limits()
+            },
+            ServoLimits {
+                min_angle: f32::NAN,
+                ..limits()
+            },
+            ServoLimits {
+                max_angle: f32::NEG_INFINITY,
+                ..limits()
+            },
+            ServoLimits {
+                min_angle: 60.0,
+                max_angle: 40.0,
+                ..limits()
+            },
+        ];
+        for limits in invalid {
+            assert!(!limits.is_valid(), "{:?}", limits);
+        }
+    }
+}
@@ -0,0 +1,593 @@
+// A small wear levelled key/value store, kept in a region of NOR flash. On the arm this is the CONFIG region at
+// the end of the flash (see devil-embedded's memory.x and storage.rs).
+//
+// The region is split into sectors, only one of which is active at a time. Records are only ever appended to
+// the active sector, so rewriting a value never erases anything. When the active sector is full, the newest
+// record of every key is copied into the next sector, which then becomes the active one. This spreads the
+// erases over every sector of the region.
+//
+// Sector layout: MAGIC (u32), generation (u32), then the records. The active sector has the newest generation,
+// generations wrap around, so newer is decided with wrapping arithmetic.
+// Record layout: key (u16), length (u16), version (u8), 0 (u8), data, crc32 of everything before it, padded
+// to 4 bytes. Erased flash reads as 0xFF, so a key of 0xFFFF marks the end of the records.
+//
+// If power is lost while a record is written, its crc doesn't match and the previous record of the key is read
+// instead. If it is lost before even the header of the record is complete, nothing after it can be trusted to
+// be erased, so the sector is treated as full and the next write moves to a fresh one.
+
+use alloc::vec::Vec;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+use log::*;
+
+/// The number of sectors the store is spread over
+pub const SECTORS: usize = 4;
+
+// Marks a sector as belonging to the store
+const MAGIC: u32 = 0xDE71_C0F6;
+
+const SECTOR_HEADER_LEN: usize = 8;
+const RECORD_HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 4;
+
+// The key erased flash reads as
+const ERASED_KEY: u16 = 0xFFFF;
+
+/// The largest value that can be stored under a key
+pub const MAX_VALUE_LEN: usize = 512;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug)]
+pub enum StoreError {
+    Flash(NorFlashErrorKind),
+    // The value is longer than MAX_VALUE_LEN
+    TooLong,
+    // Even after moving to a fresh sector the value does not fit
+    Full,
+}
+
+fn flash_error(err: impl NorFlashError) -> StoreError {
+    StoreError::Flash(err.kind())
+}
+
+// A record found in a sector
+struct RecordInfo {
+    // From the start of the sector
+    offset: usize,
+    key: u16,
+    version: u8,
+    len: usize,
+    // Whether the crc matched
+    valid: bool,
+}
+
+impl RecordInfo {
+    // The number of bytes the record takes up in the sector
+    fn size(&self) -> usize {
+        record_size(self.len)
+    }
+}
+
+fn record_size(len: usize) -> usize {
+    (RECORD_HEADER_LEN + len + CRC_LEN + 3) & !3
+}
+
+// Whether generation is newer than other, allowing for the generations wrapping around
+fn is_newer(generation: u32, other: u32) -> bool {
+    (generation.wrapping_sub(other) as i32) > 0
+}
+
+pub struct ConfigStore<F: NorFlash> {
+    flash: F,
+    // Where the region starts, as an offset into the flash
+    region_offset: u32,
+    // The index of the active sector
+    active: usize,
+    // The generation of the active sector
+    generation: u32,
+    // Where the next record goes in the active sector
+    write_offset: usize,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    /// Find the active sector in the SECTORS erase sectors from region_offset, if there is none (the first
+    /// boot) the first sector is formatted.
+    pub fn new(mut flash: F, region_offset: u32) -> Result<Self, StoreError> {
+        let mut active: Option<(usize, u32)> = None;
+        for sector in 0..SECTORS {
+            let mut header = [0u8; SECTOR_HEADER_LEN];
+            flash
+                .read(region_offset + (sector * F::ERASE_SIZE) as u32, &mut header)
+                .map_err(flash_error)?;
+
+            let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let generation = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            if magic == MAGIC && active.is_none_or(|(_, newest)| is_newer(generation, newest)) {
+                active = Some((sector, generation));
+            }
+        }
+
+        let mut store = Self {
+            flash,
+            region_offset,
+            active: 0,
+            generation: 0,
+            write_offset: SECTOR_HEADER_LEN,
+        };
+
+        match active {
+            Some((sector, generation)) => {
+                store.active = sector;
+                store.generation = generation;
+                store.write_offset = store.scan(sector)?.1;
+            }
+            None => {
+                info!("No config store found, formatting");
+                store.erase(0)?;
+                store.write_sector_header(0, 1)?;
+                store.generation = 1;
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// The flash the store lives in, for the other regions of the flash. Only one Flash can exist, so it is
+    /// reached through the store.
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Read the newest value stored under key into buf. Returns the version and length of the value, or None if
+    /// there is no valid value.
+    pub fn read(&mut self, key: u16, buf: &mut [u8]) -> Result<Option<(u8, usize)>, StoreError> {
+        let Some(record) = self.newest(self.active, key)? else {
+            return Ok(None);
+        };
+        if record.len > buf.len() {
+            return Ok(None);
+        }
+
+        let start = self.sector_offset(self.active) + (record.offset + RECORD_HEADER_LEN) as u32;
+        self.flash
+            .read(start, &mut buf[..record.len])
+            .map_err(flash_error)?;
+        Ok(Some((record.version, record.len)))
+    }
+
+    /// Store value under key, replacing whatever was there before.
+    pub fn write(&mut self, key: u16, version: u8, value: &[u8]) -> Result<(), StoreError> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(StoreError::TooLong);
+        }
+
+        if self.write_offset + record_size(value.len()) > F::ERASE_SIZE {
+            self.compact()?;
+            if self.write_offset + record_size(value.len()) > F::ERASE_SIZE {
+                return Err(StoreError::Full);
+            }
+        }
+
+        // Whatever happens to the write, the space is used, so nothing is ever written twice
+        let offset = self.write_offset;
+        self.write_offset += record_size(value.len());
+        self.append(self.active, offset, key, version, value)
+    }
+
+    // Copy the newest valid record of every key into the next sector, and make it the active sector. The header
+    // of the new sector is written last, so if power is lost half way through the old sector is still used.
+    fn compact(&mut self) -> Result<(), StoreError> {
+        let next = (self.active + 1) % SECTORS;
+        info!(
+            "Config store sector {} is full, moving to {}",
+            self.active, next
+        );
+
+        self.erase(next)?;
+
+        let records = self.records(self.active)?;
+        let mut keys: Vec<u16> = Vec::new();
+        let mut offset = SECTOR_HEADER_LEN;
+        let mut value = [0u8; MAX_VALUE_LEN];
+
+        for record in records.iter().rev().filter(|record| record.valid) {
+            if keys.contains(&record.key) {
+                continue; // A newer record of this key was already copied
+            }
+            keys.push(record.key);
+
+            let start =
+                self.sector_offset(self.active) + (record.offset + RECORD_HEADER_LEN) as u32;
+            self.flash
+                .read(start, &mut value[..record.len])
+                .map_err(flash_error)?;
+            self.append(
+                next,
+                offset,
+                record.key,
+                record.version,
+                &value[..record.len],
+            )?;
+            offset += record.size();
+        }
+
+        let generation = self.generation.wrapping_add(1);
+        self.write_sector_header(next, generation)?;
+        self.active = next;
+        self.generation = generation;
+        self.write_offset = offset;
+        Ok(())
+    }
+
+    // Write a single record at offset in sector
+    fn append(
+        &mut self,
+        sector: usize,
+        offset: usize,
+        key: u16,
+        version: u8,
+        value: &[u8],
+    ) -> Result<(), StoreError> {
+        let mut record = [0xFFu8; RECORD_HEADER_LEN + MAX_VALUE_LEN + CRC_LEN + 3];
+        let size = record_size(value.len());
+
+        record[0..2].copy_from_slice(&key.to_le_bytes());
+        record[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        record[4] = version;
+        record[5] = 0;
+        record[RECORD_HEADER_LEN..RECORD_HEADER_LEN + value.len()].copy_from_slice(value);
+
+        let crc_start = RECORD_HEADER_LEN + value.len();
+        let crc = CRC.checksum(&record[..crc_start]);
+        record[crc_start..crc_start + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        self.flash
+            .write(self.sector_offset(sector) + offset as u32, &record[..size])
+            .map_err(flash_error)
+    }
+
+    // The newest valid record of key in sector
+    fn newest(&mut self, sector: usize, key: u16) -> Result<Option<RecordInfo>, StoreError> {
+        Ok(self
+            .records(sector)?
+            .into_iter()
+            .rev()
+            .find(|record| record.valid && record.key == key))
+    }
+
+    // Every record in sector, oldest first. Records with a bad crc are included, but marked as not valid.
+    fn records(&mut self, sector: usize) -> Result<Vec<RecordInfo>, StoreError> {
+        Ok(self.scan(sector)?.0)
+    }
+
+    // The records of sector, and where the erased space after them starts. A record header that was never
+    // finished leaves no space that is known to be erased.
+    fn scan(&mut self, sector: usize) -> Result<(Vec<RecordInfo>, usize), StoreError> {
+        let mut records = Vec::new();
+        let mut offset = SECTOR_HEADER_LEN;
+        let mut buf = [0u8; RECORD_HEADER_LEN + MAX_VALUE_LEN + CRC_LEN];
+
+        while offset + RECORD_HEADER_LEN + CRC_LEN <= F::ERASE_SIZE {
+            let start = self.sector_offset(sector) + offset as u32;
+            self.flash
+                .read(start, &mut buf[..RECORD_HEADER_LEN])
+                .map_err(flash_error)?;
+
+            let key = u16::from_le_bytes([buf[0], buf[1]]);
+            let len = u16::from_le_bytes([buf[2], buf[3]]) as usize;
+            if buf[..RECORD_HEADER_LEN].iter().all(|byte| *byte == 0xFF) {
+                return Ok((records, offset));
+            }
+            if key == ERASED_KEY || len > MAX_VALUE_LEN || offset + record_size(len) > F::ERASE_SIZE
+            {
+                warn!("Unfinished record in config store sector {}", sector);
+                return Ok((records, F::ERASE_SIZE));
+            }
+
+            let crc_start = RECORD_HEADER_LEN + len;
+            self.flash
+                .read(start, &mut buf[..crc_start + CRC_LEN])
+                .map_err(flash_error)?;
+            let crc = u32::from_le_bytes([
+                buf[crc_start],
+                buf[crc_start + 1],
+                buf[crc_start + 2],
+                buf[crc_start + 3],
+            ]);
+
+            let record = RecordInfo {
+                offset,
+                key,
+                version: buf[4],
+                len,
+                valid: crc == CRC.checksum(&buf[..crc_start]),
+            };
+            if !record.valid {
+                warn!("Corrupt record for key {} in config store", key);
+            }
+
+            offset += record.size();
+            records.push(record);
+        }
+
+        Ok((records, offset))
+    }
+
+    fn write_sector_header(&mut self, sector: usize, generation: u32) -> Result<(), StoreError> {
+        let mut header = [0u8; SECTOR_HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&generation.to_le_bytes());
+        self.flash
+            .write(self.sector_offset(sector), &header)
+            .map_err(flash_error)
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), StoreError> {
+        let start = self.sector_offset(sector);
+        self.flash
+            .erase(start, start + F::ERASE_SIZE as u32)
+            .map_err(flash_error)
+    }
+
+    fn sector_offset(&self, sector: usize) -> u32 {
+        self.region_offset + (sector * F::ERASE_SIZE) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+
+    use embedded_storage::nor_flash::{ErrorType, ReadNorFlash};
+
+    use super::*;
+
+    const SECTOR_LEN: usize = 4096;
+
+    // NOR flash in memory. Like the real thing, nothing can be written twice without an erase in between, and
+    // power can be lost part way through a write.
+    struct MemFlash {
+        bytes: Vec<u8>,
+        // How many more bytes can be written before the power goes
+        writes_left: Option<usize>,
+    }
+
+    impl MemFlash {
+        fn new() -> Self {
+            Self {
+                bytes: vec![0xFF; SECTORS * SECTOR_LEN],
+                writes_left: None,
+            }
+        }
+    }
+
+    impl ErrorType for MemFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MemFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for MemFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR_LEN;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.bytes[from as usize..to as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            for (index, byte) in bytes.iter().enumerate() {
+                if self.writes_left == Some(0) {
+                    return Err(NorFlashErrorKind::Other);
+                }
+                self.writes_left = self.writes_left.map(|left| left - 1);
+
+                let address = offset as usize + index;
+                assert_eq!(
+                    self.bytes[address], 0xFF,
+                    "{} was written twice without an erase",
+                    address
+                );
+                self.bytes[address] = *byte;
+            }
+            Ok(())
+        }
+    }
+
+    // Turn the power off and on again, the store is opened afresh from what is in the flash
+    fn reboot(store: ConfigStore<MemFlash>) -> ConfigStore<MemFlash> {
+        let mut flash = store.flash;
+        flash.writes_left = None;
+        ConfigStore::new(flash, 0).unwrap()
+    }
+
+    fn read(store: &mut ConfigStore<MemFlash>, key: u16) -> Option<(u8, Vec<u8>)> {
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        let (version, len) = store.read(key, &mut buf).unwrap()?;
+        Some((version, buf[..len].to_vec()))
+    }
+
+    // Keep rewriting key with large values until the store moves on to the next sector
+    fn fill_sector(store: &mut ConfigStore<MemFlash>, key: u16) {
+        let active = store.active;
+        let mut fill = 0u8;
+        while store.active == active {
+            store.write(key, 1, &[fill; 500]).unwrap();
+            fill = fill.wrapping_add(1);
+        }
+    }
+
+    #[test]
+    fn formats_a_blank_flash() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        assert_eq!((store.active, store.generation), (0, 1));
+        assert_eq!(read(&mut store, 1), None);
+
+        let mut store = reboot(store);
+        assert_eq!((store.active, store.generation), (0, 1));
+        assert_eq!(read(&mut store, 1), None);
+    }
+
+    #[test]
+    fn newest_value_is_read_after_a_reboot() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        store.write(1, 1, b"old").unwrap();
+        store.write(2, 3, b"other").unwrap();
+        store.write(1, 2, b"new").unwrap();
+        assert_eq!(read(&mut store, 1), Some((2, b"new".to_vec())));
+
+        let mut store = reboot(store);
+        assert_eq!(read(&mut store, 1), Some((2, b"new".to_vec())));
+        assert_eq!(read(&mut store, 2), Some((3, b"other".to_vec())));
+    }
+
+    #[test]
+    fn refuses_a_value_that_is_too_long() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        assert!(matches!(
+            store.write(1, 1, &[0; MAX_VALUE_LEN + 1]),
+            Err(StoreError::TooLong)
+        ));
+    }
+
+    #[test]
+    fn compaction_keeps_the_newest_record_of_every_key() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        store.write(1, 1, b"kept").unwrap();
+        fill_sector(&mut store, 2);
+        assert_eq!((store.active, store.generation), (1, 2));
+
+        // Only the two newest records were copied, so there is plenty of room left
+        assert_eq!(store.records(1).unwrap().len(), 3);
+        assert_eq!(read(&mut store, 1), Some((1, b"kept".to_vec())));
+
+        let mut store = reboot(store);
+        assert_eq!((store.active, store.generation), (1, 2));
+        assert_eq!(read(&mut store, 1), Some((1, b"kept".to_vec())));
+    }
+
+    #[test]
+    fn compaction_goes_round_every_sector() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        store.write(1, 1, b"kept").unwrap();
+        for _ in 0..SECTORS {
+            fill_sector(&mut store, 2);
+        }
+        assert_eq!((store.active, store.generation), (0, SECTORS as u32 + 1));
+
+        let mut store = reboot(store);
+        assert_eq!(store.active, 0);
+        assert_eq!(read(&mut store, 1), Some((1, b"kept".to_vec())));
+    }
+
+    #[test]
+    fn generation_rolls_over() {
+        // A store which has been compacted nearly u32::MAX times
+        let mut flash = MemFlash::new();
+        flash.bytes[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        flash.bytes[4..8].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+
+        let mut store = ConfigStore::new(flash, 0).unwrap();
+        store.write(1, 1, b"kept").unwrap();
+        for _ in 0..3 {
+            fill_sector(&mut store, 2);
+        }
+        assert_eq!((store.active, store.generation), (3, 1));
+
+        // Sector 3 has the lowest generation, but it is the newest
+        let mut store = reboot(store);
+        assert_eq!((store.active, store.generation), (3, 1));
+        assert_eq!(read(&mut store, 1), Some((1, b"kept".to_vec())));
+    }
+
+    #[test]
+    fn bad_crc_falls_back_to_the_previous_record() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        store.write(1, 1, b"old").unwrap();
+        let newest = store.write_offset;
+        store.write(1, 1, b"new").unwrap();
+
+        // Flip a bit of the data of the newest record
+        store.flash.bytes[newest + RECORD_HEADER_LEN] ^= 0x01;
+
+        let mut store = reboot(store);
+        assert_eq!(read(&mut store, 1), Some((1, b"old".to_vec())));
+
+        // The corrupt record is skipped over, not written on top of
+        store.write(1, 1, b"newer").unwrap();
+        let mut store = reboot(store);
+        assert_eq!(read(&mut store, 1), Some((1, b"newer".to_vec())));
+    }
+
+    #[test]
+    fn torn_write_keeps_the_previous_value() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        store.write(1, 1, b"old").unwrap();
+
+        // The power goes after the header and part of the value
+        store.flash.writes_left = Some(RECORD_HEADER_LEN + 2);
+        assert!(store.write(1, 1, b"new").is_err());
+
+        let mut store = reboot(store);
+        assert_eq!(read(&mut store, 1), Some((1, b"old".to_vec())));
+        store.write(1, 1, b"newer").unwrap();
+        assert_eq!(read(&mut store, 1), Some((1, b"newer".to_vec())));
+    }
+
+    #[test]
+    fn torn_record_header_moves_to_a_fresh_sector() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        store.write(1, 1, b"old").unwrap();
+
+        // The power goes after the key, before the length, so where the record ends isn't known
+        store.flash.writes_left = Some(2);
+        assert!(store.write(1, 1, b"new").is_err());
+
+        let mut store = reboot(store);
+        assert_eq!(read(&mut store, 1), Some((1, b"old".to_vec())));
+        store.write(1, 1, b"newer").unwrap();
+        assert_eq!(store.active, 1);
+        assert_eq!(read(&mut store, 1), Some((1, b"newer".to_vec())));
+    }
+
+    #[test]
+    fn torn_compaction_keeps_the_old_sector() {
+        let mut store = ConfigStore::new(MemFlash::new(), 0).unwrap();
+        store.write(1, 1, b"kept").unwrap();
+        while store.write_offset + record_size(500) <= SECTOR_LEN {
+            store.write(2, 1, &[7; 500]).unwrap();
+        }
+
+        // The power goes while the records are copied, before the header of the new sector
+        store.flash.writes_left = Some(100);
+        assert!(store.write(2, 1, &[8; 500]).is_err());
+
+        let mut store = reboot(store);
+        assert_eq!(store.active, 0);
+        assert_eq!(read(&mut store, 1), Some((1, b"kept".to_vec())));
+        assert_eq!(read(&mut store, 2), Some((1, vec![7; 500])));
+
+        store.write(2, 1, &[8; 500]).unwrap();
+        let mut store = reboot(store);
+        assert_eq!(store.active, 1);
+        assert_eq!(read(&mut store, 1), Some((1, b"kept".to_vec())));
+        assert_eq!(read(&mut store, 2), Some((1, vec![8; 500])));
+    }
+}
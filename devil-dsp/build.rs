@@ -0,0 +1,40 @@
+//! This build script precomputes the coefficient tables of the window functions, so that the micro controller
+//! never has to compute a cosine. The tables are written into OUT_DIR/window_tables.rs, which is included by
+//! src/window.rs.
+
+use std::env;
+use std::f64::consts::PI;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+// The window lengths a table is generated for, these match the fft sizes that can be used
+const LENGTHS: [usize; 3] = [32, 64, 128];
+
+fn main() {
+    let mut tables = String::new();
+
+    for len in LENGTHS {
+        // Periodic windows are used, since they are meant to be followed by an fft
+        write_table(&mut tables, "HANN", len, |n| {
+            0.5 - 0.5 * (2.0 * PI * n / len as f64).cos()
+        });
+        write_table(&mut tables, "HAMMING", len, |n| {
+            0.54 - 0.46 * (2.0 * PI * n / len as f64).cos()
+        });
+    }
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("window_tables.rs"), tables).unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+// Writes a single `pub static NAME_LEN: [f32; LEN]` table using the function for each coefficient
+fn write_table(out: &mut String, name: &str, len: usize, coefficient: impl Fn(f64) -> f64) {
+    writeln!(out, "pub static {name}_{len}: [f32; {len}] = [").unwrap();
+    for n in 0..len {
+        writeln!(out, "    {:?},", coefficient(n as f64) as f32).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
@@ -0,0 +1,186 @@
+use emg_filter_rs::{EMGFilters, NotchFrequency, SampleFrequency};
+
+use crate::quality::QUALITY_WINDOW_LEN;
+use crate::time_domain;
+use crate::{ChannelQuality, QualityLimits, SlidingWindow};
+use crate::{
+    FeatureSet, Frame, Sample, CHANNELS, FEATURES_PER_CHANNEL, FEATURE_SET, FFT_FEATURES,
+    FRAME_LEN, WINDOW_FUNCTION, WINDOW_LEN,
+};
+
+// The number of frequency bins that come out of a real fft of WINDOW_LEN samples
+const FFT_BINS: usize = WINDOW_LEN / 2;
+
+/// Turns raw samples into frames of features. Each sample is run through Savannah's EMGFilters to remove
+/// power line noise and kept in a SlidingWindow. Every `hop` samples the features in FEATURE_SET are computed
+/// for each sensor over the last WINDOW_LEN samples.
+///
+/// The last QUALITY_WINDOW_LEN raw samples are kept as well, so the quality of each sensor can be measured
+/// alongside the features.
+pub struct FeatureExtractor {
+    filters: [EMGFilters; CHANNELS],
+    window: SlidingWindow<CHANNELS, WINDOW_LEN>,
+    raw: SlidingWindow<CHANNELS, QUALITY_WINDOW_LEN>,
+}
+
+impl Default for FeatureExtractor {
+    // Disjoint windows, a frame every WINDOW_LEN samples
+    fn default() -> Self {
+        Self::new(WINDOW_LEN)
+    }
+}
+
+impl FeatureExtractor {
+    /// Create a new FeatureExtractor with fresh filters, which outputs a frame every `hop` samples.
+    /// hop has to be between 1 and WINDOW_LEN.
+    pub fn new(hop: usize) -> Self {
+        Self {
+            // The sample frequency has to match SAMPLE_RATE_HZ
+            filters: core::array::from_fn(|_| {
+                EMGFilters::new(
+                    SampleFrequency::Hz1000,
+                    NotchFrequency::Hz60,
+                    true,
+                    true,
+                    true,
+                )
+            }),
+            window: SlidingWindow::new(hop),
+            raw: SlidingWindow::new(hop),
+        }
+    }
+
+    /// Filter a single sample from every sensor and add it to the window. Returns a frame of features
+    /// every `hop` samples, once the first WINDOW_LEN samples have been collected.
+    pub fn push(&mut self, sample: Sample) -> Option<Frame> {
+        let mut filtered = [0.0; CHANNELS];
+        for ((filter, filtered), value) in
+            self.filters.iter_mut().zip(filtered.iter_mut()).zip(sample)
+        {
+            *filtered = filter.update(value.into());
+        }
+
+        self.raw.push(sample.map(|value| value as f32));
+        if self.window.push(filtered) {
+            Some(Self::extract(&self.window.window()))
+        } else {
+            None
+        }
+    }
+
+    /// Measure the quality of each sensor over the last raw samples (see ChannelQuality::measure), this is meant
+    /// to be called whenever push returns a frame.
+    pub fn quality(&self, limits: &QualityLimits) -> [ChannelQuality; CHANNELS] {
+        self.raw
+            .window()
+            .map(|raw| ChannelQuality::measure(&raw, limits))
+    }
+
+    /// How hard each muscle is contracting, the mean absolute value of the last WINDOW_LEN filtered samples
+    pub fn activation(&self) -> [f32; CHANNELS] {
+        self.window
+            .window()
+            .map(|window| time_domain::mean_absolute_value(&window))
+    }
+
+    /// Compute the features of a window of already filtered samples, using FEATURE_SET.
+    pub fn extract(window: &[[f32; WINDOW_LEN]; CHANNELS]) -> Frame {
+        let mut frame = [0.0; FRAME_LEN];
+
+        for (measurements, features) in window
+            .iter()
+            .zip(frame.chunks_exact_mut(FEATURES_PER_CHANNEL))
+        {
+            match FEATURE_SET {
+                FeatureSet::Fft => Self::fft_features(measurements, features),
+                FeatureSet::TimeDomain => {
+                    features.copy_from_slice(&time_domain::features(measurements))
+                }
+                FeatureSet::Both => {
+                    let (fft, time) = features.split_at_mut(FFT_FEATURES);
+                    Self::fft_features(measurements, fft);
+                    time.copy_from_slice(&time_domain::features(measurements));
+                }
+            }
+        }
+
+        frame
+    }
+
+    // Write the amplitude of each frequency bin of the measurements into features
+    fn fft_features(measurements: &[f32; WINDOW_LEN], features: &mut [f32]) {
+        // The fft is done in place, so work on a copy to leave the window untouched
+        let mut measurements = *measurements;
+        WINDOW_FUNCTION.apply(&mut measurements);
+        let spectrum = microfft::real::rfft_64(&mut measurements);
+        // rfft packs the nyquist bin into the imaginary part of the dc bin, throw it away
+        spectrum[0].im = 0.0;
+
+        // Only the first FFT_BINS features are filled in, the rest stay zero, see FFT_FEATURES
+        features[..FFT_FEATURES].fill(0.0);
+        for (feature, bin) in features[..FFT_BINS].iter_mut().zip(spectrum.iter()) {
+            *feature = bin.l1_norm();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_silent_window_has_no_features() {
+        let frame = FeatureExtractor::extract(&[[0.0; WINDOW_LEN]; CHANNELS]);
+        assert!(frame.iter().all(|feature| *feature == 0.0));
+    }
+
+    #[test]
+    fn a_sine_peaks_in_its_own_bin() {
+        if FEATURE_SET == FeatureSet::TimeDomain {
+            return;
+        }
+
+        // Exactly 8 cycles a window, so it lands on bin 8, only on the second channel
+        let mut window = [[0.0; WINDOW_LEN]; CHANNELS];
+        for (n, sample) in window[1].iter_mut().enumerate() {
+            *sample = 100.0
+                * libm::sinf(2.0 * core::f32::consts::PI * 8.0 * n as f32 / WINDOW_LEN as f32);
+        }
+        let frame = FeatureExtractor::extract(&window);
+
+        let channel =
+            |index: usize| &frame[index * FEATURES_PER_CHANNEL..(index + 1) * FEATURES_PER_CHANNEL];
+        assert!(channel(0).iter().all(|feature| *feature < 1e-3));
+
+        let bins = &channel(1)[..FFT_BINS];
+        let peak = (0..FFT_BINS)
+            .max_by(|a, b| bins[*a].total_cmp(&bins[*b]))
+            .unwrap();
+        assert_eq!(peak, 8);
+        // The bins past FFT_BINS are always zero
+        assert!(channel(1)[FFT_BINS..FFT_FEATURES]
+            .iter()
+            .all(|feature| *feature == 0.0));
+    }
+
+    #[test]
+    fn frames_come_out_every_hop_once_the_window_is_full() {
+        let mut extractor = FeatureExtractor::new(16);
+        let ready: usize = (0..WINDOW_LEN + 64)
+            .filter(|_| extractor.push([2048; CHANNELS]).is_some())
+            .count();
+        assert_eq!(ready, 1 + 64 / 16);
+    }
+
+    #[test]
+    fn quality_is_measured_on_the_raw_samples() {
+        let mut extractor = FeatureExtractor::default();
+        for _ in 0..WINDOW_LEN {
+            extractor.push([0; CHANNELS]);
+        }
+        for quality in extractor.quality(&QualityLimits::new()) {
+            assert_eq!(quality.clipping_ratio, 1.0);
+            assert!(quality.flat_line);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// No Standard library is required to run this code, this is so that it can run on the micro controller.
+#![no_std]
+
+// The purpose of this crate is to turn the raw EMG samples into the features the model is trained on.
+// It is shared between devil-embedded and training, so that the chip and the training data always go
+// through the exact same pipeline. Because it is plain no_std code it can also be run on a normal computer.
+
+pub mod extractor;
+pub mod quality;
+pub mod sliding;
+pub mod time_domain;
+pub mod window;
+
+pub use extractor::FeatureExtractor;
+pub use quality::{ChannelQuality, QualityLimits};
+pub use sliding::SlidingWindow;
+pub use window::WindowFunction;
+
+use time_domain::TIME_DOMAIN_FEATURES;
+
+// The number of sensors which are sampled, devil-embedded maps each of them to a pin. The number of inputs
+// of the model follows from this.
+pub const CHANNELS: usize = 3;
+
+// The rate each sensor is sampled at in Hz, the filters in the FeatureExtractor are tuned for this rate
+pub const SAMPLE_RATE_HZ: u64 = 1000;
+
+// The number of samples from each sensor that make up a single window. This is fixed rather than configurable at
+// runtime like the hop, since the fft size, the window tables, the number of model inputs and PIPELINE_ID all
+// follow from it. SlidingWindow itself takes any length.
+pub const WINDOW_LEN: usize = 64;
+
+// The number of fft features each sensor contributes. Only the first WINDOW_LEN / 2 are real bins, the rest
+// are zero, which keeps the layout the same as the data that has already been recorded.
+pub const FFT_FEATURES: usize = WINDOW_LEN;
+
+/// The features which are computed for each sensor. Changing FEATURE_SET changes the number of inputs the
+/// model takes, so the model has to be retrained (from raw samples) afterwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureSet {
+    /// The amplitude of each frequency bin
+    Fft,
+    /// Mean absolute value, root mean square, waveform length, zero crossings and slope sign changes
+    TimeDomain,
+    /// The fft features followed by the time-domain features
+    Both,
+}
+
+impl FeatureSet {
+    /// The number of features each sensor contributes with this set
+    pub const fn features_per_channel(self) -> usize {
+        match self {
+            Self::Fft => FFT_FEATURES,
+            Self::TimeDomain => TIME_DOMAIN_FEATURES,
+            Self::Both => FFT_FEATURES + TIME_DOMAIN_FEATURES,
+        }
+    }
+}
+
+// The feature set used by both devil-embedded and training
+pub const FEATURE_SET: FeatureSet = FeatureSet::Fft;
+
+// The window function applied to the samples before the fft. Data has to be recorded with the same window the
+// model is used with, training refuses to build from a recording tagged with another window. The data recorded
+// so far is rectangular, switch this to Hann once it has been recorded again.
+pub const WINDOW_FUNCTION: WindowFunction = WindowFunction::Rectangular;
+
+// The number of inputs that each sensor contributes to the frame, they are laid out one channel after another
+pub const FEATURES_PER_CHANNEL: usize = FEATURE_SET.features_per_channel();
+
+// The number of features in a single frame, this is what the model takes in
+pub const FRAME_LEN: usize = FEATURES_PER_CHANNEL * CHANNELS;
+
+// A single sample from every sensor, as read from the ADC
+pub type Sample = [u16; CHANNELS];
+
+// A single frame of features, this is what gets handed to the model
+pub type Frame = [f32; FRAME_LEN];
+
+// Identifies the feature pipeline, a model only works with the pipeline it was trained with. This changes whenever
+// a setting that changes the features does, and is stored in the model header so a mismatch is caught at load time.
+pub const PIPELINE_ID: u32 = pipeline_id();
+
+// FNV-1a over the settings which shape the features
+const fn pipeline_id() -> u32 {
+    let settings = [
+        CHANNELS as u32,
+        SAMPLE_RATE_HZ as u32,
+        WINDOW_LEN as u32,
+        FFT_FEATURES as u32,
+        FEATURE_SET as u32,
+        WINDOW_FUNCTION as u32,
+    ];
+
+    let mut hash = 0x811c_9dc5u32;
+    let mut i = 0;
+    while i < settings.len() {
+        hash ^= settings[i];
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
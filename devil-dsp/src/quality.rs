@@ -0,0 +1,241 @@
+// Signal quality metrics of each sensor, computed from the raw (unfiltered) samples. These catch electrodes
+// lifting off, the ADC railing, and mains hum swamping the signal, so the arm doesn't act on garbage.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{SAMPLE_RATE_HZ, WINDOW_LEN};
+
+// The frequency of the power lines, the EMGFilters notch is tuned for the same frequency
+pub const MAINS_HZ: u64 = 60;
+
+// The number of raw samples the quality is measured over. The mains hum is measured at exactly MAINS_HZ, and
+// over a window this long everything more than a few Hz away barely leaks in, where a WINDOW_LEN fft would have
+// 15.6 Hz bins reaching well into the EMG band. Clipping and flat lines only look at the last WINDOW_LEN.
+pub const QUALITY_WINDOW_LEN: usize = 256;
+
+const _: () = assert!(QUALITY_WINDOW_LEN >= WINDOW_LEN);
+
+/// The limits a channel has to stay within to be considered good
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QualityLimits {
+    /// The largest value the ADC can output, samples at 0 or at this value are clipped
+    pub sample_max: u16,
+    /// The largest fraction of samples in a window that may be clipped
+    pub max_clipping_ratio: f32,
+    /// A window whose samples span less than this is flat, which usually means the electrode is off
+    pub min_peak_to_peak: f32,
+    /// The largest fraction of the power that may be in the mains band
+    pub max_mains_ratio: f32,
+}
+
+impl QualityLimits {
+    /// Limits for the RP2040's 12-bit ADC
+    pub const fn new() -> Self {
+        Self {
+            sample_max: 4095,
+            max_clipping_ratio: 0.05,
+            min_peak_to_peak: 3.0,
+            max_mains_ratio: 0.7,
+        }
+    }
+
+    /// Whether every limit is a finite number which isn't negative, anything else would make every window bad
+    /// (or good)
+    pub fn is_valid(&self) -> bool {
+        [
+            self.max_clipping_ratio,
+            self.min_peak_to_peak,
+            self.max_mains_ratio,
+        ]
+        .iter()
+        .all(|limit| limit.is_finite() && *limit >= 0.0)
+    }
+}
+
+impl Default for QualityLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The quality of a single sensor over one window
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelQuality {
+    /// The fraction of samples which were at 0 or sample_max
+    pub clipping_ratio: f32,
+    /// Whether the samples barely moved
+    pub flat_line: bool,
+    /// The fraction of the power (without dc) that is around MAINS_HZ
+    pub mains_ratio: f32,
+}
+
+impl ChannelQuality {
+    /// Measure the quality of the last QUALITY_WINDOW_LEN raw samples
+    pub fn measure(raw: &[f32; QUALITY_WINDOW_LEN], limits: &QualityLimits) -> Self {
+        let recent = &raw[QUALITY_WINDOW_LEN - WINDOW_LEN..];
+        let clipped = recent
+            .iter()
+            .filter(|x| **x <= 0.0 || **x >= limits.sample_max as f32)
+            .count();
+
+        let min = recent.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = recent.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        Self {
+            clipping_ratio: clipped as f32 / WINDOW_LEN as f32,
+            flat_line: max - min < limits.min_peak_to_peak,
+            mains_ratio: Self::mains_ratio(raw),
+        }
+    }
+
+    // The power at exactly the mains frequency, divided by the power of the whole window without dc
+    fn mains_ratio(raw: &[f32; QUALITY_WINDOW_LEN]) -> f32 {
+        // Remove the dc offset first, the ADC sits around the middle of its range
+        let mean = raw.iter().sum::<f32>() / QUALITY_WINDOW_LEN as f32;
+        let samples = raw.map(|x| x - mean);
+
+        let total: f32 = samples.iter().map(|x| x * x).sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        // A sine at MAINS_HZ has all of its power here, so the ratio is 1 for pure hum
+        let mains = 2.0 * goertzel_power(&samples, MAINS_HZ as f32) / QUALITY_WINDOW_LEN as f32;
+        (mains / total).min(1.0)
+    }
+
+    /// Whether the channel is within the limits, the arm should not move if any channel is bad
+    pub fn is_good(&self, limits: &QualityLimits) -> bool {
+        self.clipping_ratio <= limits.max_clipping_ratio
+            && !self.flat_line
+            && self.mains_ratio <= limits.max_mains_ratio
+    }
+}
+
+// The squared magnitude of the dft of samples at exactly hz, found with the Goertzel algorithm. Unlike an fft
+// the frequency doesn't have to fall on a bin.
+fn goertzel_power(samples: &[f32], hz: f32) -> f32 {
+    let coefficient = 2.0 * libm::cosf(2.0 * core::f32::consts::PI * hz / SAMPLE_RATE_HZ as f32);
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for sample in samples {
+        let s = sample + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    s1 * s1 + s2 * s2 - coefficient * s1 * s2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sine at frequency hz around the middle of the 12-bit range
+    fn sine(hz: f32, amplitude: f32) -> [f32; QUALITY_WINDOW_LEN] {
+        core::array::from_fn(|n| {
+            let t = n as f32 / SAMPLE_RATE_HZ as f32;
+            2048.0 + amplitude * libm::sinf(2.0 * core::f32::consts::PI * hz * t)
+        })
+    }
+
+    #[test]
+    fn clipping_counts_samples_at_either_rail() {
+        let limits = QualityLimits::new();
+        let mut raw = sine(250.0, 500.0);
+        raw[QUALITY_WINDOW_LEN - 4..].fill(0.0);
+        raw[QUALITY_WINDOW_LEN - 8..QUALITY_WINDOW_LEN - 4].fill(4095.0);
+        // Only the last WINDOW_LEN samples are checked for clipping
+        raw[0] = 0.0;
+
+        let quality = ChannelQuality::measure(&raw, &limits);
+        assert_eq!(quality.clipping_ratio, 8.0 / WINDOW_LEN as f32);
+        assert!(!quality.is_good(&limits));
+    }
+
+    #[test]
+    fn a_constant_signal_is_flat() {
+        let limits = QualityLimits::new();
+        let quality = ChannelQuality::measure(&[2048.0; QUALITY_WINDOW_LEN], &limits);
+        assert!(quality.flat_line);
+        assert_eq!(quality.clipping_ratio, 0.0);
+        assert_eq!(quality.mains_ratio, 0.0);
+        assert!(!quality.is_good(&limits));
+    }
+
+    #[test]
+    fn mains_hum_is_caught() {
+        let limits = QualityLimits::new();
+        let quality = ChannelQuality::measure(&sine(MAINS_HZ as f32, 500.0), &limits);
+        assert!(
+            quality.mains_ratio > 0.9,
+            "mains ratio {}",
+            quality.mains_ratio
+        );
+        assert!(!quality.is_good(&limits));
+    }
+
+    #[test]
+    fn emg_next_to_the_mains_frequency_isnt_hum() {
+        let limits = QualityLimits::new();
+        for hz in [40.0, 50.0, 70.0, 80.0] {
+            let quality = ChannelQuality::measure(&sine(hz, 500.0), &limits);
+            assert!(
+                quality.mains_ratio < 0.1,
+                "mains ratio {} at {} Hz",
+                quality.mains_ratio,
+                hz
+            );
+            assert!(quality.is_good(&limits));
+        }
+    }
+
+    #[test]
+    fn some_hum_on_a_contraction_is_tolerated() {
+        let limits = QualityLimits::new();
+        let emg = sine(80.0, 500.0);
+        let hum = sine(MAINS_HZ as f32, 300.0);
+        let raw: [f32; QUALITY_WINDOW_LEN] = core::array::from_fn(|n| emg[n] + hum[n] - 2048.0);
+
+        let quality = ChannelQuality::measure(&raw, &limits);
+        assert!(
+            quality.mains_ratio > 0.2 && quality.mains_ratio < 0.4,
+            "mains ratio {}",
+            quality.mains_ratio
+        );
+        assert!(quality.is_good(&limits));
+    }
+
+    #[test]
+    fn a_clean_signal_is_good() {
+        let limits = QualityLimits::new();
+        let quality = ChannelQuality::measure(&sine(250.0, 500.0), &limits);
+        assert!(
+            quality.mains_ratio < 0.1,
+            "mains ratio {}",
+            quality.mains_ratio
+        );
+        assert!(!quality.flat_line);
+        assert!(quality.is_good(&limits));
+    }
+
+    #[test]
+    fn limits_have_to_be_finite_and_not_negative() {
+        assert!(QualityLimits::new().is_valid());
+        for bad in [f32::NAN, f32::INFINITY, -0.1] {
+            assert!(!QualityLimits {
+                max_clipping_ratio: bad,
+                ..QualityLimits::new()
+            }
+            .is_valid());
+            assert!(!QualityLimits {
+                min_peak_to_peak: bad,
+                ..QualityLimits::new()
+            }
+            .is_valid());
+            assert!(!QualityLimits {
+                max_mains_ratio: bad,
+                ..QualityLimits::new()
+            }
+            .is_valid());
+        }
+    }
+}
@@ -0,0 +1,139 @@
+/// A ring buffer holding the last LEN samples of every sensor. Once it has filled up, it reports that a new
+/// window is ready every `hop` samples, so windows overlap by LEN - hop samples. A hop of LEN gives back
+/// disjoint windows. Both the length and the hop can be anything, the FeatureExtractor uses WINDOW_LEN.
+pub struct SlidingWindow<const CHANNELS: usize, const LEN: usize> {
+    buffer: [[f32; LEN]; CHANNELS],
+    hop: usize,
+    head: usize, // Where the next sample is written, this is also the oldest sample once full
+    filled: usize, // The number of samples in the buffer, up to LEN
+    since_last: usize, // The number of samples pushed since the last window was ready
+}
+
+impl<const CHANNELS: usize, const LEN: usize> SlidingWindow<CHANNELS, LEN> {
+    /// Create an empty SlidingWindow, hop has to be between 1 and LEN
+    pub fn new(hop: usize) -> Self {
+        assert!(
+            hop >= 1 && hop <= LEN,
+            "hop has to be between 1 and the window length"
+        );
+        Self {
+            buffer: [[0.0; LEN]; CHANNELS],
+            hop,
+            head: 0,
+            filled: 0,
+            since_last: 0,
+        }
+    }
+
+    /// The number of new samples between each window
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// Add a sample from every sensor, returns true if a new window is ready
+    pub fn push(&mut self, sample: [f32; CHANNELS]) -> bool {
+        for (buffer, value) in self.buffer.iter_mut().zip(sample) {
+            buffer[self.head] = value;
+        }
+
+        self.head = (self.head + 1) % LEN;
+        self.filled = (self.filled + 1).min(LEN);
+        self.since_last += 1;
+
+        if self.filled == LEN && self.since_last >= self.hop {
+            self.since_last = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The last LEN samples of every sensor, ordered from oldest to newest
+    pub fn window(&self) -> [[f32; LEN]; CHANNELS] {
+        core::array::from_fn(|channel| {
+            core::array::from_fn(|i| self.buffer[channel][(self.head + i) % LEN])
+        })
+    }
+
+    /// Throw away every sample, the next window is ready once LEN new samples are pushed
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.filled = 0;
+        self.since_last = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pushes samples counting up from 0 on the first channel, and down from 0 on the second. Returns whether
+    // each sample made a window ready, samples past the ones pushed are false.
+    fn ready_at<const LEN: usize>(
+        window: &mut SlidingWindow<2, LEN>,
+        samples: usize,
+    ) -> [bool; 64] {
+        let mut ready = [false; 64];
+        for (i, ready) in ready.iter_mut().enumerate().take(samples) {
+            *ready = window.push([i as f32, -(i as f32)]);
+        }
+        ready
+    }
+
+    #[test]
+    fn first_window_is_ready_once_full_then_every_hop() {
+        let mut window = SlidingWindow::<2, 8>::new(3);
+        let ready = ready_at(&mut window, 20);
+        let indices: [usize; 5] = [7, 10, 13, 16, 19];
+        for (i, ready) in ready.iter().enumerate().take(20) {
+            assert_eq!(*ready, indices.contains(&i), "sample {}", i);
+        }
+    }
+
+    #[test]
+    fn hop_of_len_gives_disjoint_windows() {
+        let mut window = SlidingWindow::<2, 4>::new(4);
+        let ready = ready_at(&mut window, 12);
+        assert_eq!(
+            &ready[..12],
+            &[false, false, false, true, false, false, false, true, false, false, false, true]
+        );
+        assert_eq!(
+            window.window(),
+            [[8.0, 9.0, 10.0, 11.0], [-8.0, -9.0, -10.0, -11.0]]
+        );
+    }
+
+    #[test]
+    fn window_is_oldest_to_newest_across_the_wrap() {
+        let mut window = SlidingWindow::<2, 4>::new(1);
+        ready_at(&mut window, 6);
+        assert_eq!(
+            window.window(),
+            [[2.0, 3.0, 4.0, 5.0], [-2.0, -3.0, -4.0, -5.0]]
+        );
+    }
+
+    #[test]
+    fn clear_waits_for_a_full_window_again() {
+        let mut window = SlidingWindow::<2, 4>::new(1);
+        ready_at(&mut window, 4);
+        window.clear();
+        assert!(!window.push([0.0; 2]));
+        assert!(!window.push([0.0; 2]));
+        assert!(!window.push([0.0; 2]));
+        assert!(window.push([0.0; 2]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn hop_of_zero_is_refused() {
+        SlidingWindow::<2, 4>::new(0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn hop_longer_than_the_window_is_refused() {
+        SlidingWindow::<2, 4>::new(5);
+    }
+}
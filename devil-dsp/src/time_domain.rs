@@ -0,0 +1,122 @@
+// The Hudgins time-domain features, these are cheap to compute and hold up better than the spectrum
+// when the electrodes shift a little. Every function takes a window of already filtered samples.
+
+// Changes smaller than this are treated as noise when counting zero crossings and slope sign changes
+pub const NOISE_THRESHOLD: f32 = 10.0;
+
+// The number of time-domain features computed for each sensor
+pub const TIME_DOMAIN_FEATURES: usize = 5;
+
+/// Compute all of the time-domain features of a window, in the order
+/// [mean absolute value, root mean square, waveform length, zero crossings, slope sign changes]
+pub fn features(window: &[f32]) -> [f32; TIME_DOMAIN_FEATURES] {
+    [
+        mean_absolute_value(window),
+        root_mean_square(window),
+        waveform_length(window),
+        zero_crossings(window, NOISE_THRESHOLD) as f32,
+        slope_sign_changes(window, NOISE_THRESHOLD) as f32,
+    ]
+}
+
+/// The average of the absolute value of each sample, basically how hard the muscle is contracting
+pub fn mean_absolute_value(window: &[f32]) -> f32 {
+    if window.is_empty() {
+        return 0.0;
+    }
+    window.iter().map(|x| libm::fabsf(*x)).sum::<f32>() / window.len() as f32
+}
+
+/// The square root of the average power of the window
+pub fn root_mean_square(window: &[f32]) -> f32 {
+    if window.is_empty() {
+        return 0.0;
+    }
+    libm::sqrtf(window.iter().map(|x| x * x).sum::<f32>() / window.len() as f32)
+}
+
+/// The total distance travelled by the signal, which captures both amplitude and frequency
+pub fn waveform_length(window: &[f32]) -> f32 {
+    window
+        .windows(2)
+        .map(|pair| libm::fabsf(pair[1] - pair[0]))
+        .sum()
+}
+
+/// The number of times the signal changes sign, ignoring crossings smaller than threshold
+pub fn zero_crossings(window: &[f32], threshold: f32) -> u32 {
+    window
+        .windows(2)
+        .filter(|pair| pair[0] * pair[1] < 0.0 && libm::fabsf(pair[0] - pair[1]) >= threshold)
+        .count() as u32
+}
+
+/// The number of times the slope of the signal changes sign, ignoring changes smaller than threshold
+pub fn slope_sign_changes(window: &[f32], threshold: f32) -> u32 {
+    window
+        .windows(3)
+        .filter(|w| {
+            let (before, after) = (w[1] - w[0], w[1] - w[2]);
+            before * after > 0.0
+                && (libm::fabsf(before) >= threshold || libm::fabsf(after) >= threshold)
+        })
+        .count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_absolute_value_of_a_known_signal() {
+        assert_eq!(mean_absolute_value(&[1.0, -1.0, 2.0, -2.0]), 1.5);
+        assert_eq!(mean_absolute_value(&[]), 0.0);
+    }
+
+    #[test]
+    fn root_mean_square_of_a_square_wave_is_its_amplitude() {
+        assert_eq!(root_mean_square(&[3.0, -3.0, 3.0, -3.0]), 3.0);
+        assert_eq!(root_mean_square(&[]), 0.0);
+    }
+
+    #[test]
+    fn waveform_length_adds_up_every_step() {
+        assert_eq!(waveform_length(&[0.0, 1.0, -1.0, 2.0]), 6.0);
+        assert_eq!(waveform_length(&[5.0]), 0.0);
+    }
+
+    #[test]
+    fn zero_crossings_ignore_noise() {
+        assert_eq!(
+            zero_crossings(&[20.0, -20.0, 20.0, -20.0], NOISE_THRESHOLD),
+            3
+        );
+        // Crossing zero by less than the threshold doesn't count
+        assert_eq!(zero_crossings(&[1.0, -1.0, 1.0, -1.0], NOISE_THRESHOLD), 0);
+        // Touching zero isn't crossing it
+        assert_eq!(zero_crossings(&[20.0, 0.0, 20.0], NOISE_THRESHOLD), 0);
+    }
+
+    #[test]
+    fn slope_sign_changes_ignore_noise() {
+        assert_eq!(
+            slope_sign_changes(&[0.0, 20.0, 0.0, 20.0, 0.0], NOISE_THRESHOLD),
+            3
+        );
+        assert_eq!(
+            slope_sign_changes(&[0.0, 1.0, 0.0, 1.0, 0.0], NOISE_THRESHOLD),
+            0
+        );
+        // A straight line never changes slope
+        assert_eq!(
+            slope_sign_changes(&[0.0, 20.0, 40.0, 60.0], NOISE_THRESHOLD),
+            0
+        );
+    }
+
+    #[test]
+    fn features_are_in_order() {
+        let window = [20.0, -20.0, 20.0, -20.0];
+        assert_eq!(features(&window), [20.0, 20.0, 120.0, 3.0, 2.0]);
+    }
+}
@@ -0,0 +1,173 @@
+// Window functions which are applied to the samples before the fft. Without one, the edges of the window
+// cause spectral leakage which smears the power of one frequency across many bins.
+
+// The precomputed coefficient tables, these are generated by build.rs
+mod tables {
+    include!(concat!(env!("OUT_DIR"), "/window_tables.rs"));
+}
+
+// Recorded data starts with a line like `#window=hann`, saying which window the features were computed with
+pub const WINDOW_TAG: &str = "#window=";
+
+/// The window functions that can be applied before the fft
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowFunction {
+    /// No window at all, the samples go straight into the fft
+    Rectangular,
+    Hann,
+    Hamming,
+}
+
+impl WindowFunction {
+    /// The coefficients of this window for a window of len samples. Returns None for the rectangular window,
+    /// and for lengths that have no precomputed table.
+    pub fn coefficients(self, len: usize) -> Option<&'static [f32]> {
+        match (self, len) {
+            (Self::Rectangular, _) => None,
+            (Self::Hann, 32) => Some(&tables::HANN_32),
+            (Self::Hann, 64) => Some(&tables::HANN_64),
+            (Self::Hann, 128) => Some(&tables::HANN_128),
+            (Self::Hamming, 32) => Some(&tables::HAMMING_32),
+            (Self::Hamming, 64) => Some(&tables::HAMMING_64),
+            (Self::Hamming, 128) => Some(&tables::HAMMING_128),
+            _ => None,
+        }
+    }
+
+    /// Multiply the samples by the window in place.
+    pub fn apply(self, samples: &mut [f32]) {
+        if self == Self::Rectangular {
+            return;
+        }
+
+        let coefficients = self
+            .coefficients(samples.len())
+            .expect("No window table for this length, add it to build.rs");
+        for (sample, coefficient) in samples.iter_mut().zip(coefficients) {
+            *sample *= coefficient;
+        }
+    }
+
+    /// The name of the window, this is used to tag recorded data
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rectangular => "rectangular",
+            Self::Hann => "hann",
+            Self::Hamming => "hamming",
+        }
+    }
+
+    /// The window with the given name, see name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rectangular" => Some(Self::Rectangular),
+            "hann" => Some(Self::Hann),
+            "hamming" => Some(Self::Hamming),
+            _ => None,
+        }
+    }
+
+    /// Read the window tag at the start of recorded data. Data without a tag was recorded before windows
+    /// were added, so it is rectangular. Returns None if the tag names an unknown window.
+    pub fn from_tag(data: &str) -> Option<Self> {
+        match data
+            .lines()
+            .next()
+            .and_then(|line| line.trim().strip_prefix(WINDOW_TAG))
+        {
+            Some(name) => Self::from_name(name.trim()),
+            None => Some(Self::Rectangular),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LENGTHS: [usize; 3] = [32, 64, 128];
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn hann_is_periodic_and_peaks_in_the_middle() {
+        for len in LENGTHS {
+            let table = WindowFunction::Hann.coefficients(len).unwrap();
+            assert_eq!(table.len(), len);
+            assert!(close(table[0], 0.0));
+            assert!(close(table[len / 2], 1.0));
+            assert!(close(table[len / 4], 0.5));
+            // Periodic, so the table is symmetric about the middle with the last point left off
+            for n in 1..len {
+                assert!(close(table[n], table[len - n]), "hann {} at {}", len, n);
+            }
+        }
+    }
+
+    #[test]
+    fn hamming_doesnt_reach_zero() {
+        for len in LENGTHS {
+            let table = WindowFunction::Hamming.coefficients(len).unwrap();
+            assert_eq!(table.len(), len);
+            assert!(close(table[0], 0.08));
+            assert!(close(table[len / 2], 1.0));
+            for n in 1..len {
+                assert!(close(table[n], table[len - n]), "hamming {} at {}", len, n);
+            }
+        }
+    }
+
+    #[test]
+    fn rectangular_and_unknown_lengths_have_no_table() {
+        assert_eq!(WindowFunction::Rectangular.coefficients(64), None);
+        assert_eq!(WindowFunction::Hann.coefficients(48), None);
+    }
+
+    #[test]
+    fn apply_multiplies_by_the_table() {
+        let mut samples = [2.0; 64];
+        WindowFunction::Hann.apply(&mut samples);
+        assert!(close(samples[0], 0.0));
+        assert!(close(samples[32], 2.0));
+
+        let mut samples = [2.0; 64];
+        WindowFunction::Rectangular.apply(&mut samples);
+        assert_eq!(samples, [2.0; 64]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for window in [
+            WindowFunction::Rectangular,
+            WindowFunction::Hann,
+            WindowFunction::Hamming,
+        ] {
+            assert_eq!(WindowFunction::from_name(window.name()), Some(window));
+        }
+        assert_eq!(WindowFunction::from_name("blackman"), None);
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(
+            WindowFunction::from_tag("#window=hamming\n1,2,fist"),
+            Some(WindowFunction::Hamming)
+        );
+        assert_eq!(
+            WindowFunction::from_tag("#window= hann \n"),
+            Some(WindowFunction::Hann)
+        );
+        // Data from before the tag was added
+        assert_eq!(
+            WindowFunction::from_tag("1,2,fist\n"),
+            Some(WindowFunction::Rectangular)
+        );
+        assert_eq!(
+            WindowFunction::from_tag(""),
+            Some(WindowFunction::Rectangular)
+        );
+        assert_eq!(WindowFunction::from_tag("#window=blackman\n"), None);
+    }
+}
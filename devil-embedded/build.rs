@@ -8,7 +8,7 @@
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
 
-use devil_ml_model::ARTIFACT_DIR;
+use devil_ml_model::{ModelHeader, ARTIFACT_DIR};
 use std::env;
 use std::fs::File;
 use std::io::Write;
@@ -39,4 +39,23 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 
     println!("cargo:rustc-env=ARTIFACT_DIR={}", ARTIFACT_DIR);
+
+    // The model built into the firmware is loaded at boot with nothing to fall back on, so one trained for a
+    // different pipeline, number of inputs or without the rejection class fails the build here instead
+    let model_path = PathBuf::from(ARTIFACT_DIR).join("model.bin");
+    println!("cargo:rerun-if-changed={}", model_path.display());
+    let model = std::fs::read(&model_path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read {}: {}, train a model first",
+            model_path.display(),
+            e
+        )
+    });
+    if let Err(err) = ModelHeader::decode(&model).and_then(|(header, _)| header.validate()) {
+        panic!(
+            "The model in {} can't be used with this firmware, it has to be retrained: {:?}",
+            model_path.display(),
+            err
+        );
+    }
 }
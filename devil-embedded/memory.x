@@ -1,6 +1,12 @@
 MEMORY {
 BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 64K - 16K
+
+/* A model uploaded over USB, see src/model_partition.rs */
+MODEL : ORIGIN = 0x101EC000, LENGTH = 64K
+
+/* The last four 4K sectors of the flash hold the ConfigStore, see src/storage.rs */
+CONFIG : ORIGIN = 0x101FC000, LENGTH = 16K
 
 /* Pick one of the two options for RAM layout     */
 
@@ -0,0 +1,87 @@
+// The action map is kept in the ConfigStore, and can be changed over serial while the arm runs. The map itself
+// and how labels are turned into motions are in devil_arm::action, so they can be tested off the chip.
+
+use alloc::string::String;
+
+use devil_arm::action::parse_motion;
+pub use devil_arm::action::{
+    Action, ActionMap, ActionMapper, Delays, Keyframe, Motion, Pose, Trigger,
+};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use log::*;
+
+use crate::config::{self, ConfigError, Record, CONFIG};
+
+// Set when the map is changed over serial, the main loop picks up the new map from here
+pub static ACTION_MAP_UPDATE: Signal<CriticalSectionRawMutex, ActionMap> = Signal::new();
+
+// Set by the gesture command, the main loop plays the motion once
+pub static GESTURE_REQUEST: Signal<CriticalSectionRawMutex, Motion> = Signal::new();
+
+impl Record for ActionMap {
+    const KEY: u16 = 4;
+    const NAME: &'static str = "actions";
+    const VERSION: u8 = 2;
+}
+
+/// Handle an action command from serial, one of
+/// - `action list`
+/// - `action set <label> <hold|toggle> <keyframe> ...`, more than one keyframe makes a sequence. A keyframe is
+///   `thumb,fingers,arm[~move_ms][>thumb,fingers,arm delays][@hold_ms]`, see Keyframe
+/// - `action set <label> <hold|toggle> grip <open> <closed>`, a proportional grip
+/// - `action remove <label>`
+/// - `action rest <thumb,fingers,arm>`
+///
+/// Changes are saved, and used straight away.
+pub async fn handle_command(args: &str) -> Result<(), ConfigError> {
+    let mut store = CONFIG.lock().await;
+    let store = store.as_mut().ok_or(ConfigError::NotReady)?;
+    let mut map: ActionMap = config::load(store);
+
+    let mut args = args.split_whitespace();
+    match args.next().unwrap_or("") {
+        "list" => {
+            info!("rest: {:?}", map.rest);
+            for action in map.actions.iter() {
+                info!("{}: {:?} {:?}", action.label, action.trigger, action.motion);
+            }
+            return Ok(());
+        }
+        "set" => {
+            let label = args.next().ok_or(ConfigError::BadArgs)?;
+            let trigger = match args.next() {
+                Some("hold") => Trigger::Hold,
+                Some("toggle") => Trigger::Toggle,
+                _ => return Err(ConfigError::BadArgs),
+            };
+            let motion = parse_motion(args).ok_or(ConfigError::BadArgs)?;
+
+            let action = Action {
+                label: String::from(label),
+                motion,
+                trigger,
+            };
+            match map.find(label) {
+                Some(index) => map.actions[index] = action,
+                None => map.actions.push(action),
+            }
+        }
+        "remove" => {
+            let label = args.next().ok_or(ConfigError::BadArgs)?;
+            map.actions.retain(|action| action.label != label);
+        }
+        "rest" => {
+            map.rest = args
+                .next()
+                .and_then(|pose| pose.parse().ok())
+                .ok_or(ConfigError::BadArgs)?;
+        }
+        _ => return Err(ConfigError::BadArgs),
+    }
+
+    config::save(store, &map)?;
+    info!("Action map saved");
+    ACTION_MAP_UPDATE.signal(map);
+    Ok(())
+}
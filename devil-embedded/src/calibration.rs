@@ -0,0 +1,175 @@
+// Calibration for the current wearer. Every wearer, and every time the sleeve is put on, has a different resting
+// noise and maximum voluntary contraction (MVC). The calibration records both for each sensor, and is stored in
+// the ConfigStore so it survives a reboot.
+
+use devil_dsp::CHANNELS;
+use devil_ml_model::{ChannelStats, Normalization};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use embassy_sync::channel::Receiver;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::sensor::Amplitudes;
+
+// How long the wearer is asked to rest, and then to contract as hard as they can
+const PHASE_DURATION: Duration = Duration::from_secs(5);
+
+// Set by the serial Handler to stop a calibration part way through, the old calibration is kept
+pub static CALIBRATION_CANCEL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The calibration of each sensor for the current wearer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// The activation of each sensor while resting
+    pub noise_floor: [f32; CHANNELS],
+    /// The activation of each sensor at maximum voluntary contraction
+    pub peak: [f32; CHANNELS],
+    /// The mean and standard deviation of the features of each sensor, used by the z-score normalization
+    pub stats: ChannelStats,
+}
+
+impl Calibration {
+    /// The normalization to use, this fills in the calibrated statistics if it is a z-score normalization
+    pub fn normalization(&self, normalization: Normalization) -> Normalization {
+        match normalization {
+            Normalization::ZScore(_) => Normalization::ZScore(self.stats),
+            other => other,
+        }
+    }
+
+    /// The activation a sensor has to be above for the muscle to count as being used. fraction is how far
+    /// between the noise floor and the peak that is, see Thresholds.
+    pub fn threshold(&self, channel: usize, fraction: f32) -> f32 {
+        self.noise_floor[channel] + fraction * (self.peak[channel] - self.noise_floor[channel])
+    }
+
+    /// How hard a muscle is contracting, 0 at the noise floor and 1 at the peak
+    pub fn level(&self, channel: usize, activation: f32) -> f32 {
+        let range = self.peak[channel] - self.noise_floor[channel];
+        if range <= 0.0 {
+            return 0.0;
+        }
+        ((activation - self.noise_floor[channel]) / range).clamp(0.0, 1.0)
+    }
+
+    /// Whether any muscle is being used, if not the wearer is resting and the arm should stay where it is
+    pub fn is_active(&self, activation: &[f32; CHANNELS], fraction: f32) -> bool {
+        activation
+            .iter()
+            .enumerate()
+            .any(|(channel, activation)| *activation > self.threshold(channel, fraction))
+    }
+}
+
+// Keeps a running mean and variance of the features of each sensor, with Welford's algorithm
+struct RunningStats {
+    count: [f32; CHANNELS],
+    mean: [f32; CHANNELS],
+    m2: [f32; CHANNELS],
+}
+
+impl RunningStats {
+    fn new() -> Self {
+        Self {
+            count: [0.0; CHANNELS],
+            mean: [0.0; CHANNELS],
+            m2: [0.0; CHANNELS],
+        }
+    }
+
+    fn add(&mut self, channel: usize, value: f32) {
+        self.count[channel] += 1.0;
+        let delta = value - self.mean[channel];
+        self.mean[channel] += delta / self.count[channel];
+        self.m2[channel] += delta * (value - self.mean[channel]);
+    }
+
+    fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            mean: self.mean,
+            std_dev: core::array::from_fn(|channel| {
+                if self.count[channel] < 2.0 {
+                    1.0
+                } else {
+                    num_traits::Float::sqrt(self.m2[channel] / (self.count[channel] - 1.0))
+                }
+            }),
+        }
+    }
+}
+
+// Receive frames for PHASE_DURATION, reporting progress every second. Returns the average activation of each
+// sensor over the phase, the features are added to stats. Returns None if the calibration was cancelled.
+async fn record_phase(
+    name: &str,
+    rx: &Receiver<'static, ThreadModeRawMutex, Amplitudes, 64>,
+    stats: &mut RunningStats,
+) -> Option<[f32; CHANNELS]> {
+    // Throw away whatever was recorded before the phase started
+    while rx.try_receive().is_ok() {}
+
+    let start = Instant::now();
+    let mut last_report = 0;
+    let mut activation = [0f32; CHANNELS];
+    let mut frames = 0u32;
+
+    while start.elapsed() < PHASE_DURATION {
+        let amplitudes = rx.receive().await;
+        if CALIBRATION_CANCEL.try_take().is_some() {
+            info!("Calibration: cancelled");
+            return None;
+        }
+
+        for (channel, features) in amplitudes
+            .features
+            .chunks_exact(devil_dsp::FEATURES_PER_CHANNEL)
+            .enumerate()
+        {
+            features.iter().for_each(|value| stats.add(channel, *value));
+            activation[channel] += amplitudes.activation[channel];
+        }
+        frames += 1;
+
+        let seconds = start.elapsed().as_secs();
+        if seconds != last_report {
+            last_report = seconds;
+            info!(
+                "Calibration: {} {}%",
+                name,
+                (start.elapsed().as_millis() * 100 / PHASE_DURATION.as_millis()).min(100)
+            );
+        }
+    }
+
+    Some(activation.map(|sum| sum / frames.max(1) as f32))
+}
+
+/// Walk the wearer through calibration, first resting then contracting as hard as they can, with the progress
+/// reported over serial. Returns None if it was cancelled.
+pub async fn run(
+    rx: &Receiver<'static, ThreadModeRawMutex, Amplitudes, 64>,
+) -> Option<Calibration> {
+    let mut stats = RunningStats::new();
+    // A cancel sent while nothing was being calibrated doesn't count
+    CALIBRATION_CANCEL.reset();
+
+    info!("Calibration: relax your arm");
+    let noise_floor = record_phase("rest", rx, &mut stats).await?;
+
+    info!("Calibration: contract as hard as you can");
+    let peak = record_phase("contraction", rx, &mut stats).await?;
+
+    info!("Calibration: done, relax");
+    info!(
+        "Calibration: noise floor {:?}, peak {:?}",
+        noise_floor, peak
+    );
+
+    Some(Calibration {
+        noise_floor,
+        peak,
+        stats: stats.stats(),
+    })
+}
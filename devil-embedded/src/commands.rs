@@ -0,0 +1,382 @@
+// The commands understood over USB. Every command is registered in COMMANDS, which `help` prints, and answers
+// with a Reply: a code saying whether it worked (shared with the host, see devil_protocol::Code) and a message.
+// A command typed on the log port has its reply logged as a line, `<name> ok: <message>` or
+// `<name> failed with code <code> (<name of the code>): <message>`. One sent as a Command::Run on the data port
+// also gets its reply back as a Message::Reply.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+
+use devil_protocol::{Code, Command, Message};
+use embassy_rp::rom_data::reset_to_usb_boot;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use log::*;
+
+use crate::action::{self, ActionMap, GESTURE_REQUEST};
+use crate::calibration::CALIBRATION_CANCEL;
+use crate::config::{self, ConfigError, CONFIG};
+use crate::mode::{self, Mode, MODE_REQUEST};
+use crate::model_partition::{self, UploadError};
+use crate::motion::{self, MotionConfig, MOTION_COMMANDS};
+use crate::recording::{RecordRequest, RECORD_REQUEST};
+use crate::stream;
+
+// Which model is being used, set by main once the model is loaded, see Inferer::describe
+pub static MODEL_VERSION: Mutex<CriticalSectionRawMutex, Option<String>> = Mutex::new(None);
+
+/// The answer to a command
+pub struct Reply {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Reply {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            code: Code::Ok,
+            message: message.into(),
+        }
+    }
+
+    pub fn err(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ConfigError> for Reply {
+    fn from(err: ConfigError) -> Self {
+        let code = match err {
+            ConfigError::NotReady => Code::NotReady,
+            ConfigError::UnknownRecord | ConfigError::BadHex | ConfigError::BadArgs => {
+                Code::BadArgs
+            }
+            ConfigError::Store(_) | ConfigError::Encode(_) => Code::Failed,
+        };
+        Self::err(code, format!("{:?}", err))
+    }
+}
+
+impl From<UploadError> for Reply {
+    fn from(err: UploadError) -> Self {
+        let code = match err {
+            UploadError::NotReady => Code::NotReady,
+            UploadError::BadArgs => Code::BadArgs,
+            _ => Code::Failed,
+        };
+        Self::err(code, format!("{:?}", err))
+    }
+}
+
+type CommandFuture<'a> = Pin<Box<dyn Future<Output = Reply> + 'a>>;
+
+/// A command, run is given everything after the name
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    run: fn(&str) -> CommandFuture<'_>,
+}
+
+/// Every command, in the order help lists them
+pub static COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "help",
+        usage: "help",
+        help: "list the commands",
+        run: help_command,
+    },
+    CommandInfo {
+        name: "hello",
+        usage: "hello",
+        help: "check the arm is listening",
+        run: hello_command,
+    },
+    CommandInfo {
+        name: "version",
+        usage: "version",
+        help: "the version of the firmware and the model being used",
+        run: version_command,
+    },
+    CommandInfo {
+        name: "stream",
+        usage: "stream <features|samples|predictions> <on|off>",
+        help: "start or stop sending a stream on the data port",
+        run: stream_command,
+    },
+    CommandInfo {
+        name: "mode",
+        usage: "mode [idle|stream|calibrate|manual|autonomous]",
+        help: "print or change the operating mode",
+        run: mode_command,
+    },
+    CommandInfo {
+        name: "servo",
+        usage: "servo <thumb|fingers|arm> <degree>",
+        help: "move a single servo, in manual mode",
+        run: servo_command,
+    },
+    CommandInfo {
+        name: "gesture",
+        usage: "gesture <label|rest>",
+        help: "play the motion the action map has for a label, in manual mode",
+        run: gesture_command,
+    },
+    CommandInfo {
+        name: "calibrate",
+        usage: "calibrate [start|stop]",
+        help: "calibrate the sensors for the wearer, or stop a calibration part way through",
+        run: calibrate_command,
+    },
+    CommandInfo {
+        name: "config",
+        usage: "config <get|set|reset> <name> [hex]",
+        help: "read or write a config record",
+        run: config_command,
+    },
+    CommandInfo {
+        name: "action",
+        usage: "action <list|set|remove|rest> ...",
+        help: "change what the arm does for each label",
+        run: action_command,
+    },
+    CommandInfo {
+        name: "model",
+        usage: "model <begin|data|end|erase> ...",
+        help: "upload a model, used by the trainer",
+        run: model_command,
+    },
+    CommandInfo {
+        name: "reboot",
+        usage: "reboot [bootloader]",
+        help: "restart the arm, into the USB bootloader if asked",
+        run: reboot_command,
+    },
+];
+
+/// Run a command line, logging the reply as well as returning it
+pub async fn run(line: &str) -> Reply {
+    let line = line.trim();
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+    let reply = match COMMANDS
+        .iter()
+        .find(|command| command.name.eq_ignore_ascii_case(name))
+    {
+        Some(command) => (command.run)(args.trim()).await,
+        None => Reply::err(Code::UnknownCommand, "send help for the list of commands"),
+    };
+
+    match reply.code {
+        Code::Ok => info!("{} ok: {}", name, reply.message),
+        code => error!(
+            "{} failed with code {} ({:?}): {}",
+            name, code as u8, code, reply.message
+        ),
+    }
+    reply
+}
+
+/// Handle a command the host sent on the data port
+pub async fn handle(command: Command) {
+    match command {
+        Command::Ping => stream::STATUS_REQUEST.signal(()),
+        Command::Stream { stream, enabled } => stream::set_enabled(stream, enabled),
+        Command::Run { id, line } => {
+            let reply = run(&line).await;
+            stream::send(&Message::Reply {
+                id,
+                code: reply.code,
+                message: reply.message,
+            });
+        }
+        // The main loop checks the mode and does the recording, see recording.rs
+        Command::Record {
+            recording,
+            label,
+            frames,
+        } => RECORD_REQUEST.signal(RecordRequest::Start {
+            recording,
+            label,
+            frames,
+        }),
+        Command::StopRecording => RECORD_REQUEST.signal(RecordRequest::Stop),
+    }
+}
+
+fn help_command(_: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        for command in COMMANDS {
+            info!("{} - {}", command.usage, command.help);
+        }
+        let names: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+        Reply::ok(names.join(" "))
+    })
+}
+
+fn hello_command(_: &str) -> CommandFuture<'_> {
+    Box::pin(async move { Reply::ok("World!") })
+}
+
+fn version_command(_: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        let model = MODEL_VERSION.lock().await;
+        Reply::ok(format!(
+            "firmware {}, protocol {}, {}",
+            env!("CARGO_PKG_VERSION"),
+            devil_protocol::VERSION,
+            model.as_deref().unwrap_or("no model loaded yet")
+        ))
+    })
+}
+
+fn stream_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match stream::handle_command(args) {
+            Ok(()) => Reply::ok(args),
+            Err(()) => Reply::err(
+                Code::BadArgs,
+                "expected stream <features|samples|predictions> <on|off>",
+            ),
+        }
+    })
+}
+
+// The servos are only moved by commands in manual mode, otherwise they would fight the predictions
+fn require_manual() -> Result<(), Reply> {
+    match mode::current() {
+        Mode::Manual => Ok(()),
+        _ => Err(Reply::err(
+            Code::Busy,
+            "only in manual mode, send mode manual first",
+        )),
+    }
+}
+
+fn mode_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match mode::handle_command(args) {
+            Ok(mode) => Reply::ok(mode.name()),
+            Err(()) => Reply::err(
+                Code::BadArgs,
+                "expected idle, stream, calibrate, manual or autonomous",
+            ),
+        }
+    })
+}
+
+fn servo_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        if let Err(reply) = require_manual() {
+            return reply;
+        }
+
+        let mut args = args.split_whitespace();
+        let index = match args.next() {
+            Some("thumb") => 0,
+            Some("fingers") => 1,
+            Some("arm") => 2,
+            _ => return Reply::err(Code::BadArgs, "expected thumb, fingers or arm"),
+        };
+        let Some(degree) = args.next().and_then(|degree| degree.parse::<f32>().ok()) else {
+            return Reply::err(Code::BadArgs, "expected an angle in degrees");
+        };
+
+        // Moved the same way as everything else, at the pace of the motion config
+        let motion_config: MotionConfig = match CONFIG.lock().await.as_mut() {
+            Some(store) => config::load(store),
+            None => MotionConfig::default(),
+        };
+        let target = motion::Move {
+            degree,
+            timing: motion_config.timing,
+            curve: motion_config.curve,
+        };
+        match MOTION_COMMANDS[index].try_send(motion::Command::Move(target)) {
+            Ok(()) => Reply::ok(format!("moving to {}", degree)),
+            Err(_) => Reply::err(Code::Busy, "the motion planner is behind"),
+        }
+    })
+}
+
+fn gesture_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        if let Err(reply) = require_manual() {
+            return reply;
+        }
+
+        let map: ActionMap = match CONFIG.lock().await.as_mut() {
+            Some(store) => config::load(store),
+            None => return Reply::from(ConfigError::NotReady),
+        };
+        match map.motion(args) {
+            Some(motion) => {
+                GESTURE_REQUEST.signal(motion);
+                Reply::ok(format!("playing {}", args))
+            }
+            None => Reply::err(Code::BadArgs, format!("no action for {}", args)),
+        }
+    })
+}
+
+fn calibrate_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match args {
+            // The main loop picks this up, and walks through the calibration
+            "" | "start" => {
+                MODE_REQUEST.signal(Mode::Calibrate);
+                Reply::ok("starting calibration")
+            }
+            "stop" => {
+                CALIBRATION_CANCEL.signal(());
+                Reply::ok("stopping calibration")
+            }
+            _ => Reply::err(Code::BadArgs, "expected start or stop"),
+        }
+    })
+}
+
+fn config_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match config::handle_command(args).await {
+            Ok(message) => Reply::ok(message),
+            Err(err) => err.into(),
+        }
+    })
+}
+
+fn action_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match action::handle_command(args).await {
+            Ok(()) => Reply::ok("action map saved"),
+            Err(err) => err.into(),
+        }
+    })
+}
+
+fn model_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match model_partition::handle_command(args).await {
+            Ok(()) => Reply::ok(args.split_whitespace().next().unwrap_or("").to_string()),
+            Err(err) => err.into(),
+        }
+    })
+}
+
+fn reboot_command(args: &str) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match args {
+            "bootloader" => reset_to_usb_boot(0, 0),
+            "" => cortex_m::peripheral::SCB::sys_reset(),
+            _ => return Reply::err(Code::BadArgs, "expected nothing, or bootloader"),
+        }
+        Reply::ok("rebooting")
+    })
+}
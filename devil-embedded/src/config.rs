@@ -0,0 +1,246 @@
+// The typed configuration of the arm, which is kept in the ConfigStore so that it can be changed over USB
+// without reflashing. Every record has a key, and a version which is bumped whenever its layout changes.
+// If a record is missing, corrupt, or from an older version, its default is used instead.
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+pub use devil_arm::servo::ServoLimits;
+use devil_dsp::QualityLimits;
+use devil_ml_model::SmoothingConfig;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use log::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::action::ActionMap;
+use crate::calibration::Calibration;
+use crate::grip::GripConfig;
+use crate::motion::MotionConfig;
+use crate::storage::{ConfigStore, StoreError, MAX_VALUE_LEN};
+
+// The store shared between the main loop and the serial Handler, it is None until main sets it up
+pub static CONFIG: Mutex<CriticalSectionRawMutex, Option<ConfigStore>> = Mutex::new(None);
+
+/// A typed value kept in the ConfigStore
+pub trait Record: Serialize + DeserializeOwned + Default + core::fmt::Debug {
+    /// The key the record is stored under, this has to be unique
+    const KEY: u16;
+    /// The name of the record, as used by the config command
+    const NAME: &'static str;
+    /// Bump this whenever the layout of the record changes, older versions are then ignored
+    const VERSION: u8;
+
+    /// Whether the values can be used, a stored record which isn't valid is ignored and one sent over serial is
+    /// refused
+    fn is_valid(&self) -> bool {
+        true
+    }
+}
+
+/// The limits of the thumb, four fingers and arm servos
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServoConfig {
+    pub thumb: ServoLimits,
+    pub fingers: ServoLimits,
+    pub arm: ServoLimits,
+}
+
+impl Record for ServoConfig {
+    const KEY: u16 = 1;
+    const NAME: &'static str = "servos";
+    const VERSION: u8 = 2;
+
+    fn is_valid(&self) -> bool {
+        self.thumb.is_valid() && self.fingers.is_valid() && self.arm.is_valid()
+    }
+}
+
+/// The thresholds used to decide whether the arm may move
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Thresholds {
+    /// The limits each sensor's signal has to stay within
+    pub quality: QualityLimits,
+    /// How far between the calibrated noise floor and peak a sensor has to be, for the muscle to count as used
+    pub activation: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            quality: crate::sensor::QUALITY_LIMITS,
+            activation: 0.1,
+        }
+    }
+}
+
+impl Record for Thresholds {
+    const KEY: u16 = 2;
+    const NAME: &'static str = "thresholds";
+    const VERSION: u8 = 2;
+
+    fn is_valid(&self) -> bool {
+        self.quality.is_valid() && self.activation.is_finite() && self.activation >= 0.0
+    }
+}
+
+impl Record for Calibration {
+    const KEY: u16 = 3;
+    const NAME: &'static str = "calibration";
+    const VERSION: u8 = 1;
+}
+
+impl Record for SmoothingConfig {
+    const KEY: u16 = 5;
+    const NAME: &'static str = "smoothing";
+    const VERSION: u8 = 1;
+
+    fn is_valid(&self) -> bool {
+        SmoothingConfig::is_valid(self)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Store(StoreError),
+    // The store hasn't been set up yet
+    NotReady,
+    Encode(postcard::Error),
+    UnknownRecord,
+    // The value given over serial isn't valid hex
+    BadHex,
+    // The arguments of a command are missing or can't be parsed
+    BadArgs,
+}
+
+impl From<StoreError> for ConfigError {
+    fn from(err: StoreError) -> Self {
+        Self::Store(err)
+    }
+}
+
+/// Read a record from the store, falling back to the default if it is missing or corrupt
+pub fn load<R: Record>(store: &mut ConfigStore) -> R {
+    try_load(store).unwrap_or_else(|| {
+        info!("No valid {} config stored, using the default", R::NAME);
+        R::default()
+    })
+}
+
+/// Read a record from the store, returns None if it is missing, corrupt, from an older version or not valid
+pub fn try_load<R: Record>(store: &mut ConfigStore) -> Option<R> {
+    let mut buf = [0u8; MAX_VALUE_LEN];
+    let record: R = match store.read(R::KEY, &mut buf) {
+        Ok(Some((version, len))) if version == R::VERSION => {
+            postcard::from_bytes(&buf[..len]).ok()?
+        }
+        Ok(_) => return None,
+        Err(err) => {
+            error!(
+                "Failed to read {} from the config store: {:?}",
+                R::NAME,
+                err
+            );
+            return None;
+        }
+    };
+    if !record.is_valid() {
+        warn!("The stored {} config isn't valid: {:?}", R::NAME, record);
+        return None;
+    }
+    Some(record)
+}
+
+/// Write a record to the store
+pub fn save<R: Record>(store: &mut ConfigStore, record: &R) -> Result<(), ConfigError> {
+    let mut buf = [0u8; MAX_VALUE_LEN];
+    let bytes = postcard::to_slice(record, &mut buf).map_err(ConfigError::Encode)?;
+    store.write(R::KEY, R::VERSION, bytes)?;
+    Ok(())
+}
+
+// Print the stored (or default) value of a record, returns its encoded bytes as hex
+fn get<R: Record>(store: &mut ConfigStore) -> Result<String, ConfigError> {
+    let record: R = load(store);
+    let mut buf = [0u8; MAX_VALUE_LEN];
+    let bytes = postcard::to_slice(&record, &mut buf).map_err(ConfigError::Encode)?;
+
+    let mut hex = String::with_capacity(2 * bytes.len());
+    for byte in bytes.iter() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    info!("{} = {:?}", R::NAME, record);
+    Ok(hex)
+}
+
+// Decode the hex as a record, and store it
+fn set<R: Record>(store: &mut ConfigStore, hex: &str) -> Result<String, ConfigError> {
+    let mut buf = [0u8; MAX_VALUE_LEN];
+    let len = decode_hex(hex, &mut buf).ok_or(ConfigError::BadHex)?;
+    let record: R = postcard::from_bytes(&buf[..len]).map_err(ConfigError::Encode)?;
+    if !record.is_valid() {
+        return Err(ConfigError::BadArgs);
+    }
+    save(store, &record)?;
+    Ok(format!("{} = {:?}, reboot to apply", R::NAME, record))
+}
+
+// Put a record back to its default
+fn reset<R: Record>(store: &mut ConfigStore) -> Result<String, ConfigError> {
+    save(store, &R::default())?;
+    Ok(format!(
+        "{} reset to {:?}, reboot to apply",
+        R::NAME,
+        R::default()
+    ))
+}
+
+fn decode_hex(hex: &str, buf: &mut [u8]) -> Option<usize> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() % 2 != 0 || hex.len() / 2 > buf.len() {
+        return None;
+    }
+    for (byte, pair) in buf.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(hex.len() / 2)
+}
+
+/// Handle a config command from serial, one of
+/// `config get <name>`, `config set <name> <hex>` or `config reset <name>`. Returns what to reply with, the
+/// encoded record as hex for get.
+pub async fn handle_command(args: &str) -> Result<String, ConfigError> {
+    let mut store = CONFIG.lock().await;
+    let store = store.as_mut().ok_or(ConfigError::NotReady)?;
+
+    let mut args = args.split_whitespace();
+    let (action, name) = (args.next().unwrap_or(""), args.next().unwrap_or(""));
+    let value = args.next().unwrap_or("");
+
+    // Runs the action for whichever record is named
+    macro_rules! dispatch {
+        ($($record:ty),*) => {
+            match name {
+                $(<$record>::NAME => match action {
+                    "get" => get::<$record>(store),
+                    "set" => set::<$record>(store, value),
+                    "reset" => reset::<$record>(store),
+                    _ => Err(ConfigError::BadArgs),
+                },)*
+                _ => Err(ConfigError::UnknownRecord),
+            }
+        };
+    }
+
+    dispatch!(
+        ServoConfig,
+        Thresholds,
+        Calibration,
+        ActionMap,
+        SmoothingConfig,
+        GripConfig,
+        MotionConfig
+    )
+}
@@ -1,39 +1,22 @@
 #![allow(unused)]
-use embassy_rp::pio::Instance;
+use embassy_time::{Duration, Timer};
 
-use crate::servo::Servo;
+use crate::action::{Delays, Keyframe, Motion, Pose};
+use crate::servo::Joint;
+
+// How long the built in gestures take to move into their pose
+const GESTURE_MOVE_MS: u32 = 500;
 
 // Gestures struct, which contains all the servos used to construct the arm.
-pub struct Gestures<
-    'd,
-    T1: Instance,
-    const SM1: usize,
-    T2: Instance,
-    const SM2: usize,
-    T3: Instance,
-    const SM3: usize,
-> {
-    thumb_servo: Servo<'d, T1, SM1>,
-    fingers_servo: Servo<'d, T2, SM2>,
-    arm_servo: Servo<'d, T3, SM3>,
+pub struct Gestures<T: Joint, F: Joint, A: Joint> {
+    thumb_servo: T,
+    fingers_servo: F,
+    arm_servo: A,
 }
 
-impl<
-        'd,
-        T1: Instance,
-        const SM1: usize,
-        T2: Instance,
-        const SM2: usize,
-        T3: Instance,
-        const SM3: usize,
-    > Gestures<'d, T1, SM1, T2, SM2, T3, SM3>
-{
+impl<T: Joint, F: Joint, A: Joint> Gestures<T, F, A> {
     /// Create a new Gestures struct
-    pub fn new(
-        thumb_servo: Servo<'d, T1, SM1>,
-        fingers_servo: Servo<'d, T2, SM2>,
-        arm_servo: Servo<'d, T3, SM3>,
-    ) -> Self {
+    pub fn new(thumb_servo: T, fingers_servo: F, arm_servo: A) -> Self {
         Self {
             thumb_servo,
             fingers_servo,
@@ -55,18 +38,91 @@ impl<
         self.arm_servo.stop();
     }
 
+    /// Move every servo to the angles of the pose
+    pub fn set_pose(&mut self, pose: &Pose) {
+        self.thumb_servo.rotate(pose.thumb.into());
+        self.fingers_servo.rotate(pose.fingers.into());
+        self.arm_servo.rotate(pose.arm.into());
+    }
+
+    /// Move every servo to the pose over duration_ms, they all start and arrive at the same time
+    pub fn transition(&mut self, pose: &Pose, duration_ms: u32) {
+        self.thumb_servo.rotate_over(pose.thumb.into(), duration_ms);
+        self.fingers_servo
+            .rotate_over(pose.fingers.into(), duration_ms);
+        self.arm_servo.rotate_over(pose.arm.into(), duration_ms);
+    }
+
+    /// Move into a keyframe, starting each servo after its delay, and return once the pose has been held
+    pub async fn keyframe(&mut self, keyframe: &Keyframe) {
+        let delays = keyframe.delays;
+        let mut order = [(delays.thumb, 0), (delays.fingers, 1), (delays.arm, 2)];
+        order.sort_unstable();
+
+        // When the last servo gets there, counted from the start of the keyframe. Without a move_ms each servo
+        // goes at the pace of its motion config, so it takes as long as its joint says the move will take.
+        let mut finished = 0;
+        let mut elapsed = 0;
+        for (delay, joint) in order {
+            Timer::after(Duration::from_millis((delay - elapsed).into())).await;
+            elapsed = delay;
+
+            let pose = &keyframe.pose;
+            let move_ms = match (joint, keyframe.move_ms) {
+                (_, Some(move_ms)) => move_ms,
+                (0, None) => self.thumb_servo.move_ms(pose.thumb.into()),
+                (1, None) => self.fingers_servo.move_ms(pose.fingers.into()),
+                (_, None) => self.arm_servo.move_ms(pose.arm.into()),
+            };
+            finished = finished.max(delay.saturating_add(move_ms));
+
+            match (joint, keyframe.move_ms) {
+                (0, Some(move_ms)) => self.thumb_servo.rotate_over(pose.thumb.into(), move_ms),
+                (1, Some(move_ms)) => self.fingers_servo.rotate_over(pose.fingers.into(), move_ms),
+                (_, Some(move_ms)) => self.arm_servo.rotate_over(pose.arm.into(), move_ms),
+                (0, None) => self.thumb_servo.rotate(pose.thumb.into()),
+                (1, None) => self.fingers_servo.rotate(pose.fingers.into()),
+                (_, None) => self.arm_servo.rotate(pose.arm.into()),
+            }
+        }
+
+        let remaining = (finished - elapsed).saturating_add(keyframe.hold_ms);
+        Timer::after(Duration::from_millis(remaining.into())).await;
+    }
+
+    /// Do a motion from the action map, a sequence goes through each keyframe in turn. A grip starts open, after
+    /// that it is up to the caller to follow the activation, see grip.rs.
+    pub async fn play(&mut self, motion: &Motion) {
+        match motion {
+            Motion::Pose(pose) => self.set_pose(pose),
+            Motion::Grip { open, .. } => self.set_pose(open),
+            Motion::Sequence(keyframes) => {
+                for keyframe in keyframes {
+                    self.keyframe(keyframe).await;
+                }
+            }
+        }
+    }
+
     /// Make the arm create a thumbs up
     pub fn thumbs_up(&mut self) {
-        self.thumb_servo.rotate(0);
-        self.fingers_servo
-            .rotate(self.thumb_servo.max_degree_rotation);
-        self.arm_servo.rotate(self.thumb_servo.max_degree_rotation);
+        let max = self.thumb_servo.max_degree_rotation() as u16;
+        self.transition(&Pose::new(0, max, max), GESTURE_MOVE_MS);
     }
 
-    /// Make the arm create a pinch
-    pub fn pinch(&mut self) {
-        self.thumb_servo.rotate(90);
-        self.fingers_servo.rotate(90);
-        self.arm_servo.rotate(self.thumb_servo.max_degree_rotation);
+    /// Make the arm create a pinch, the fingers wait for the thumb to get halfway so they don't hit it
+    pub async fn pinch(&mut self) {
+        let max = self.thumb_servo.max_degree_rotation() as u16;
+        self.keyframe(&Keyframe {
+            pose: Pose::new(90, 90, max),
+            move_ms: Some(GESTURE_MOVE_MS),
+            delays: Delays {
+                thumb: 0,
+                fingers: GESTURE_MOVE_MS / 2,
+                arm: 0,
+            },
+            hold_ms: 0,
+        })
+        .await;
     }
 }
@@ -0,0 +1,113 @@
+// Proportional grip control. Once the action map selects a grip (see Motion::Grip), how far the hand closes
+// follows how hard the wearer contracts, instead of jumping straight to a pose. The activation of one sensor is
+// scaled by the calibration, smoothed, and then drives either the position of the grip or the speed it closes at.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Record;
+
+/// How the activation drives the grip
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GripMode {
+    /// The harder the contraction, the further the grip is closed. Relaxing opens it again.
+    Position,
+    /// The harder the contraction, the faster the grip closes. Relaxing holds it where it is.
+    Speed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GripConfig {
+    /// The sensor which drives the grip
+    pub channel: u8,
+    pub mode: GripMode,
+    /// The activation is multiplied by this, a higher gain closes the grip fully with less effort
+    pub gain: f32,
+    /// Activation levels below this are ignored, so a resting muscle doesn't move the grip
+    pub deadband: f32,
+    /// The most of the grip that can close or open in a second, 1.0 is fully in a second
+    pub max_rate: f32,
+    /// How much of each new activation is mixed into the smoothed level, between 0 and 1. Lower is smoother.
+    pub smoothing: f32,
+}
+
+impl Default for GripConfig {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            mode: GripMode::Position,
+            gain: 1.0,
+            deadband: 0.05,
+            max_rate: 2.0,
+            smoothing: 0.2,
+        }
+    }
+}
+
+impl Record for GripConfig {
+    const KEY: u16 = 6;
+    const NAME: &'static str = "grip";
+    const VERSION: u8 = 1;
+
+    // A negative or NaN rate would stop the grip from ever moving, or worse
+    fn is_valid(&self) -> bool {
+        let fraction = 0.0..=1.0;
+        self.gain.is_finite()
+            && self.gain >= 0.0
+            && self.max_rate.is_finite()
+            && self.max_rate >= 0.0
+            && fraction.contains(&self.smoothing)
+            && fraction.contains(&self.deadband)
+    }
+}
+
+pub struct ProportionalGrip {
+    config: GripConfig,
+    // The smoothed activation level
+    level: f32,
+    // How far the grip is closed, 0 is open and 1 is closed
+    closure: f32,
+}
+
+impl ProportionalGrip {
+    pub fn new(config: GripConfig) -> Self {
+        Self {
+            config,
+            level: 0.0,
+            closure: 0.0,
+        }
+    }
+
+    /// The sensor which drives the grip
+    pub fn channel(&self) -> usize {
+        self.config.channel as usize
+    }
+
+    /// Start again from an open grip
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+        self.closure = 0.0;
+    }
+
+    /// Feed in the calibrated activation of the grip's sensor (0 at rest, 1 at maximum contraction), and the
+    /// seconds since the last update. Returns how far the grip should be closed, between 0 and 1.
+    pub fn update(&mut self, activation: f32, dt: f32) -> f32 {
+        let smoothing = self.config.smoothing.clamp(0.0, 1.0);
+        self.level += smoothing * (activation.clamp(0.0, 1.0) - self.level);
+
+        // Remove the deadband, and stretch what is left back out to between 0 and 1
+        let deadband = self.config.deadband.clamp(0.0, 0.99);
+        let level = ((self.level - deadband) / (1.0 - deadband)).max(0.0);
+
+        // Never below 0, or the clamp below would panic
+        let max_step = (self.config.max_rate * dt).max(0.0);
+        let step = match self.config.mode {
+            GripMode::Position => {
+                ((self.config.gain * level).min(1.0) - self.closure).clamp(-max_step, max_step)
+            }
+            GripMode::Speed => (self.config.gain * level).min(1.0) * max_step,
+        };
+
+        self.closure = (self.closure + step).clamp(0.0, 1.0);
+        self.closure
+    }
+}
@@ -1,8 +1,15 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
 use burn::{backend::NdArray, tensor::Tensor};
-use devil_ml_model::Model;
+use devil_ml_model::{LabelSet, Model, ModelHeader, Normalization};
+use log::*;
+
+use crate::model_partition;
+use crate::storage::DevilFlash;
 
 // Add the model into the program at compile time, this should be found in the build directory in /model/model.bin
-// It is put there by the build.rs script.
+// It is put there by the build.rs script. This is only used if no model has been uploaded over USB.
 static MODEL_BYTES: &[u8] = include_bytes!(concat!(env!("ARTIFACT_DIR"), "/model.bin"));
 
 static mut MODEL: Option<Model<Backend>> = None;
@@ -13,12 +20,66 @@ pub type BackendDeice = <Backend as burn::tensor::backend::Backend>::Device;
 
 pub struct Inferer<B: burn::prelude::Backend> {
     model: Model<B>,
+    header: ModelHeader,
+    // Whether the model is the one uploaded over USB
+    uploaded: bool,
 }
 
 impl<B: burn::prelude::Backend> Inferer<B> {
-    pub fn new(device: &B::Device) -> Self {
-        let model = Model::from_embedded(device, MODEL_BYTES);
-        Inferer { model }
+    // Load the model uploaded over USB if there is one that can be used, otherwise the one built into the firmware
+    pub fn new(device: &B::Device, flash: &mut DevilFlash) -> Self {
+        let uploaded =
+            model_partition::load(flash).and_then(|uploaded| {
+                match Model::from_embedded(device, &uploaded) {
+                    Ok(loaded) => {
+                        info!("Using the uploaded model");
+                        Some(loaded)
+                    }
+                    Err(err) => {
+                        error!("The uploaded model can't be used: {:?}", err);
+                        None
+                    }
+                }
+            });
+
+        // build.rs refuses to build the firmware with a built in model whose header doesn't match, so this only
+        // fails if the weights themselves are corrupt
+        let is_uploaded = uploaded.is_some();
+        let (model, header) = uploaded.unwrap_or_else(|| {
+            Model::from_embedded(device, MODEL_BYTES)
+                .expect("The built in model doesn't match the firmware")
+        });
+        Inferer {
+            model,
+            header,
+            uploaded: is_uploaded,
+        }
+    }
+
+    // Which model is being used, for the version command
+    pub fn describe(&self) -> String {
+        let labels: Vec<&str> = self.header.labels.iter().collect();
+        format!(
+            "{} model, pipeline {:08x}, data {:016x}, labels {}",
+            if self.uploaded {
+                "uploaded"
+            } else {
+                "built in"
+            },
+            self.header.pipeline,
+            self.header.data_hash,
+            labels.join(",")
+        )
+    }
+
+    // How the inputs have to be normalized before they are given to the model
+    pub fn normalization(&self) -> Normalization {
+        self.header.normalization
+    }
+
+    // The label of each output of the model
+    pub fn labels(&self) -> &LabelSet {
+        &self.header.labels
     }
 
     pub fn infer(&self, item: Tensor<B, 1>) -> Tensor<B, 1> {
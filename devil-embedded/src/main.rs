@@ -1,13 +1,15 @@
 #![no_std]
 #![no_main]
 
-use core::time::Duration;
-
 extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
 use burn::backend::NdArray;
 use burn::tensor::activation::softmax;
 use burn::tensor::Tensor;
-use devil_ml_model::MODEL_INPUTS;
+use devil_dsp::{CHANNELS, PIPELINE_ID, WINDOW_FUNCTION};
+use devil_ml_model::{Smoother, SmoothingConfig, MODEL_INPUTS};
+use devil_protocol::{Message, Outcome, Status, Stream};
 use embassy_executor::Spawner;
 use embassy_rp::adc::{Adc, Config as AdcConfig, InterruptHandler as AdcInterruptHandler};
 use embassy_rp::gpio;
@@ -17,26 +19,55 @@ use embassy_rp::pio::{InterruptHandler as PioInterruptHandler, Pio};
 use embassy_rp::pio_programs::pwm::{PioPwm, PioPwmProgram};
 use embassy_rp::usb::{Driver, InterruptHandler as UsbInterruptHandler};
 use embassy_rp::{adc, bind_interrupts};
-use embassy_time::Timer;
+use embassy_time::{Duration, Instant};
 use gpio::{Level, Output};
 
+use action::{ActionMap, ActionMapper, Motion, Pose, ACTION_MAP_UPDATE, GESTURE_REQUEST};
+use calibration::Calibration;
+use config::{ServoConfig, Thresholds, CONFIG};
+use grip::{GripConfig, ProportionalGrip};
 use infer::{Backend, BackendDeice, Inferer};
-use sensor::{read_adc_value, CHANNEL_AMPLITUDES};
+use mode::{Mode, ModeMachine, MODE_REQUEST};
+use motion::{MotionConfig, MotionPlanner, PlannedJoint, MOTION_COMMANDS};
+use recording::{RecordRequest, Recording, RECORD_REQUEST};
+#[cfg(not(feature = "ads1299"))]
+use sensor::OnChipAdc;
+use sensor::{read_adc_value, ACHIEVED_SAMPLE_RATE, CHANNEL_AMPLITUDES, OVERRUNS};
 use serial::usb_task;
-use servo::ServoBuilder;
+use servo::{Servo, ServoBuilder};
+use storage::{ConfigStore, DevilFlash};
+#[cfg(feature = "ads1299")]
+use {
+    embassy_rp::spi::{self, Spi},
+    embassy_time::Delay,
+    embedded_hal_bus::spi::ExclusiveDevice,
+    sensor::Ads1299,
+};
 
 use log::*;
 use {defmt_rtt as _, panic_probe as _};
 
 use embedded_alloc::LlffHeap as Heap;
+use portable_atomic::Ordering;
 
 use gesture::Gestures;
 
+mod action;
+mod calibration;
+mod commands;
+mod config;
 mod gesture;
+mod grip;
 mod infer;
+mod mode;
+mod model_partition;
+mod motion;
+mod recording;
 mod sensor;
 mod serial;
 mod servo;
+mod storage;
+mod stream;
 
 // Sets up an allocator to be used, without this, you cannot put things on the heap, no vectors!
 #[global_allocator]
@@ -49,6 +80,34 @@ bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => PioInterruptHandler<PIO0>;
 });
 
+// How often a Status is sent to the host
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Where the motion planners think each servo is before their first move
+const START_POSITION: f32 = 0.0;
+
+// The motion planner of each servo, see motion.rs. Tasks can't be generic, so there is one for each servo.
+#[embassy_executor::task]
+async fn thumb_motion(servo: Servo<'static, PIO0, 0>) {
+    MotionPlanner::new(servo, START_POSITION)
+        .run(MOTION_COMMANDS[0].receiver())
+        .await
+}
+
+#[embassy_executor::task]
+async fn fingers_motion(servo: Servo<'static, PIO0, 1>) {
+    MotionPlanner::new(servo, START_POSITION)
+        .run(MOTION_COMMANDS[1].receiver())
+        .await
+}
+
+#[embassy_executor::task]
+async fn arm_motion(servo: Servo<'static, PIO0, 2>) {
+    MotionPlanner::new(servo, START_POSITION)
+        .run(MOTION_COMMANDS[2].receiver())
+        .await
+}
+
 // This is the main function for the program. Where execution starts.
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
@@ -73,11 +132,62 @@ async fn main(spawner: Spawner) {
     // Defining the pins that are to be used with the program
     // Note that the LED pin on the Pico W is PIN_16
     let mut led = Output::new(p.PIN_25, Level::Low);
-    // These are the pins for the sensors
-    let adc = Adc::new(p.ADC, Irqs, AdcConfig::default());
-    let p26 = adc::Channel::new_pin(p.PIN_26, Pull::None);
-    let p27 = adc::Channel::new_pin(p.PIN_27, Pull::None);
-    let p28 = adc::Channel::new_pin(p.PIN_28, Pull::None);
+    // Open the config store, everything that can be changed without reflashing is kept there
+    let mut store = ConfigStore::new(DevilFlash::new_blocking(p.FLASH), storage::REGION_OFFSET)
+        .expect("Failed to open the config store");
+    let servo_config: ServoConfig = config::load(&mut store);
+    let thresholds: Thresholds = config::load(&mut store);
+    let action_map: ActionMap = config::load(&mut store);
+    let smoothing: SmoothingConfig = config::load(&mut store);
+    let grip_config: GripConfig = config::load(&mut store);
+    let motion_config: MotionConfig = config::load(&mut store);
+    // Load the calibration of the wearer, it can be redone at any time with the "calibrate" command
+    let mut calibration: Option<Calibration> = config::try_load(&mut store);
+    if calibration.is_none() {
+        warn!("No calibration found, send \"calibrate\" to calibrate");
+    }
+
+    // Initialize the NdArray backend, and load the model. One uploaded over USB is used over the built in one.
+    let device = BackendDeice::default();
+    let inferer = Inferer::new(&device, store.flash());
+    *commands::MODEL_VERSION.lock().await = Some(inferer.describe());
+
+    // Share the store with the serial Handler, so the config can be changed over USB
+    *CONFIG.lock().await = Some(store);
+
+    // These are the pins for the sensors, one for each of devil_dsp::CHANNELS. If the number of sensors changes,
+    // change CHANNELS in devil-dsp and the pins here, the model inputs follow automatically.
+    #[cfg(not(feature = "ads1299"))]
+    let sensor_source = {
+        let adc = Adc::new(p.ADC, Irqs, AdcConfig::default());
+        let sensor_channels = [
+            adc::Channel::new_pin(p.PIN_26, Pull::None),
+            adc::Channel::new_pin(p.PIN_27, Pull::None),
+            adc::Channel::new_pin(p.PIN_28, Pull::None),
+        ];
+        OnChipAdc::new(adc, sensor_channels)
+    };
+
+    // With the ads1299 feature the sensors are read from an external ADC on SPI0 instead, its DRDY pin is on
+    // PIN_20. The ADS1299 uses SPI mode 1.
+    #[cfg(feature = "ads1299")]
+    let sensor_source = {
+        let mut spi_config = spi::Config::default();
+        spi_config.frequency = 4_000_000;
+        spi_config.phase = spi::Phase::CaptureOnSecondTransition;
+        spi_config.polarity = spi::Polarity::IdleLow;
+
+        let spi = Spi::new(
+            p.SPI0, p.PIN_18, p.PIN_19, p.PIN_16, p.DMA_CH0, p.DMA_CH1, spi_config,
+        );
+        let cs = Output::new(p.PIN_17, Level::High);
+        let drdy = gpio::Input::new(p.PIN_20, Pull::Up);
+        let spi = ExclusiveDevice::new(spi, cs, Delay).unwrap();
+
+        Ads1299::new(spi, drdy)
+            .await
+            .expect("Failed to start the ADS1299")
+    };
 
     // This defines a Servo, not really in use rn, but it will be more integrated in the final code,
     // Mostly detached for easy testing
@@ -90,79 +200,231 @@ async fn main(spawner: Spawner) {
     } = Pio::new(p.PIO0, Irqs);
     let prg = PioPwmProgram::new(&mut common);
 
-    let servo_degree_rotation = 180;
-    let servo_max_pulse_width = Duration::from_micros(500);
-    let servo_min_pulse_width = Duration::from_micros(2500);
-
     let pwm_pio = PioPwm::new(&mut common, sm0, p.PIN_2, &prg);
-    let mut thumb_servo = ServoBuilder::new(pwm_pio)
-        .set_max_degree_rotation(servo_degree_rotation) // Example of adjusting values for MG996R servo
-        .set_min_pulse_width(servo_max_pulse_width) // This value was detemined by a rough experiment.
-        .set_max_pulse_width(servo_min_pulse_width) // Along with this value.
+    let thumb_servo = ServoBuilder::new(pwm_pio)
+        .set_limits(servo_config.thumb)
         .build();
 
     let pwm_pio = PioPwm::new(&mut common, sm1, p.PIN_3, &prg);
-    let mut four_fingers_servo = ServoBuilder::new(pwm_pio)
-        .set_max_degree_rotation(servo_degree_rotation)
-        .set_min_pulse_width(servo_max_pulse_width)
-        .set_max_pulse_width(servo_min_pulse_width)
+    let four_fingers_servo = ServoBuilder::new(pwm_pio)
+        .set_limits(servo_config.fingers)
         .build();
 
     let pwm_pio = PioPwm::new(&mut common, sm2, p.PIN_4, &prg);
-    let mut arm_servo = ServoBuilder::new(pwm_pio)
-        .set_max_degree_rotation(servo_degree_rotation)
-        .set_min_pulse_width(servo_max_pulse_width)
-        .set_max_pulse_width(servo_min_pulse_width)
+    let arm_servo = ServoBuilder::new(pwm_pio)
+        .set_limits(servo_config.arm)
         .build();
 
-    let mut gestures = Gestures::new(thumb_servo, four_fingers_servo, arm_servo);
+    // Each servo is moved smoothly by its own planner task, Gestures sends them the targets
+    spawner.spawn(thumb_motion(thumb_servo)).unwrap();
+    spawner.spawn(fingers_motion(four_fingers_servo)).unwrap();
+    spawner.spawn(arm_motion(arm_servo)).unwrap();
+
+    let [thumb_commands, fingers_commands, arm_commands] = &MOTION_COMMANDS;
+    let mut gestures = Gestures::new(
+        PlannedJoint::new(
+            thumb_commands,
+            servo_config.thumb.max_degree_rotation,
+            motion_config,
+            START_POSITION,
+        ),
+        PlannedJoint::new(
+            fingers_commands,
+            servo_config.fingers.max_degree_rotation,
+            motion_config,
+            START_POSITION,
+        ),
+        PlannedJoint::new(
+            arm_commands,
+            servo_config.arm.max_degree_rotation,
+            motion_config,
+            START_POSITION,
+        ),
+    );
 
     gestures.start();
 
-    // further integration of the gestures could involve a speed parameter or a transition time
+    // Maps each predicted label to what the arm does, start off at rest
+    gestures.set_pose(&action_map.rest);
+    let mut actions = ActionMapper::new(action_map);
+
+    // Steadies the predictions before they are acted on
+    let mut smoother = Smoother::new(smoothing);
+
+    // Follows the contraction while a grip is selected, grip_poses holds the open and closed pose of the grip
+    let mut grip = ProportionalGrip::new(grip_config);
+    let mut grip_poses: Option<(Pose, Pose)> = None;
+    let mut last_frame = Instant::now();
+    let mut last_status = Instant::now();
 
     // spawn the task that reads the ADC value
     spawner
         .spawn(read_adc_value(
-            adc,
-            p26,
-            p27,
-            p28,
+            sensor_source,
+            thresholds.quality,
             CHANNEL_AMPLITUDES.sender(), // This is the channel which sends the data between "processes"
         ))
         .unwrap();
 
     let rx_adv_value = CHANNEL_AMPLITUDES.receiver(); // This is where the channel recieves the data
-    led.set_high(); // turn on the led
 
-    info!("Getting started");
+    // A button can change the mode as well as the mode command, see mode.rs
+    #[cfg(feature = "mode-button")]
+    spawner
+        .spawn(mode::mode_button(gpio::Input::new(p.PIN_15, Pull::Up)))
+        .unwrap();
 
-    // Initialize the NdArray backend
-    let device = BackendDeice::default();
+    // The arm starts off autonomous
+    let mut modes = ModeMachine::new();
+    // The labelled recording being made for the host, see recording.rs
+    let mut recording: Option<Recording> = None;
+    led.set_high(); // turn on the led
 
-    let inferer = Inferer::new(&device);
+    info!("Getting started");
 
     let mut degree = 0;
 
     loop {
         degree = (degree + 1) % 120;
 
+        // Change mode when asked to, see mode.rs
+        if let Some(requested) = MODE_REQUEST.try_take() {
+            if let Some(from) = modes.change(requested) {
+                // A recording is only made while in stream mode
+                if let Some(cancelled) = recording.take() {
+                    cancelled.finish(Outcome::Cancelled).await;
+                }
+                mode::exit(from, &mut gestures, &actions.rest()).await;
+                mode::enter(modes.mode(), &mut gestures);
+
+                // Whatever was being predicted is forgotten, the arm is at rest
+                smoother.reset();
+                actions.reset();
+                grip_poses = None;
+            }
+        }
+
+        // Start or stop a recording when the host asks, a new recording ends the one before it
+        if let Some(request) = RECORD_REQUEST.try_take() {
+            if let Some(cancelled) = recording.take() {
+                cancelled.finish(Outcome::Cancelled).await;
+            }
+            match request {
+                RecordRequest::Start {
+                    recording: number,
+                    label,
+                    frames,
+                } if modes.mode() == Mode::Stream => {
+                    // The frames waiting in the channel are from before the wearer was asked to do anything
+                    while rx_adv_value.try_receive().is_ok() {}
+                    recording = Some(Recording::new(number, label, frames));
+                }
+                RecordRequest::Start {
+                    recording: number, ..
+                } => Recording::refuse(number),
+                RecordRequest::Stop => (),
+            }
+        }
+
+        // Calibrate, then store the result and go back to the mode from before
+        if modes.mode() == Mode::Calibrate {
+            if let Some(new_calibration) = calibration::run(&rx_adv_value).await {
+                if let Some(store) = CONFIG.lock().await.as_mut() {
+                    match config::save(store, &new_calibration) {
+                        Ok(()) => info!("Calibration saved"),
+                        Err(err) => error!("Failed to save the calibration: {:?}", err),
+                    }
+                }
+                calibration = Some(new_calibration);
+            }
+            let previous = modes.finish_calibration();
+            mode::enter(previous, &mut gestures);
+        }
+
+        // Play a gesture asked for over serial, which is only done in manual mode. The command checks the mode
+        // too, but the mode may have changed since, so a request left over from manual mode is dropped.
+        if let Some(motion) = GESTURE_REQUEST.try_take() {
+            if modes.mode() == Mode::Manual {
+                info!("Playing {:?}", motion);
+                gestures.play(&motion).await;
+                grip_poses = None;
+            } else {
+                warn!(
+                    "Not playing {:?}, gestures are only played in manual mode",
+                    motion
+                );
+            }
+        }
+
         info!("before inputs in loop");
-        // Convert the u32 into f32, these really should be normalized between 0 and 1.
-        let inputs: [f32; MODEL_INPUTS] = rx_adv_value.receive().await.map(|x| x as f32);
-        let inputs_min = inputs.into_iter().reduce(f32::min).unwrap(); // get the min of inputs
-        let inputs_max = inputs.into_iter().reduce(f32::max).unwrap(); // get the max of inputs
+        // Receive the next frame of features from the sensors
+        let amplitudes = rx_adv_value.receive().await;
+        let raw_inputs: [f32; MODEL_INPUTS] = amplitudes.features;
+
+        // Check every sensor is giving a usable signal, if not the arm must not move
+        let mut signal_ok = true;
+        for (channel, quality) in amplitudes.quality.iter().enumerate() {
+            if !quality.is_good(&thresholds.quality) {
+                warn!(
+                    "Bad signal on channel {}: clipping {}, flat line {}, mains {}",
+                    channel, quality.clipping_ratio, quality.flat_line, quality.mains_ratio
+                );
+                signal_ok = false;
+            }
+        }
 
-        let inputs = inputs.map(|x| (x - inputs_min) / (inputs_max - inputs_min)); // normalize the input
+        // Let the host know how things are going every so often, or when it asks
+        if stream::STATUS_REQUEST.try_take().is_some() || last_status.elapsed() >= STATUS_INTERVAL {
+            last_status = Instant::now();
+            stream::send(&Message::Status(Status {
+                firmware: String::from(env!("CARGO_PKG_VERSION")),
+                pipeline: PIPELINE_ID,
+                window: String::from(WINDOW_FUNCTION.name()),
+                uptime_ms: Instant::now().as_millis(),
+                mode: modes.mode(),
+                sample_rate: ACHIEVED_SAMPLE_RATE.load(Ordering::Relaxed),
+                overruns: OVERRUNS.load(Ordering::Relaxed),
+                calibrated: calibration.is_some(),
+                signal_ok,
+            }));
+        }
 
-        info!("after inputs nomalization");
+        if !mode::streams(modes.mode()) {
+            continue;
+        }
+
+        // The raw inputs are what gets recorded to train on, training normalizes them itself with the same
+        // strategy. See devil-protocol for how they are sent.
+        if stream::enabled(Stream::Features) {
+            stream::send(&Message::Features {
+                sequence: amplitudes.sequence,
+                features: raw_inputs.to_vec(),
+            });
+        }
+
+        if let Some(active) = &mut recording {
+            if active.record(amplitudes.sequence, &raw_inputs) {
+                if let Some(complete) = recording.take() {
+                    complete.finish(Outcome::Complete).await;
+                }
+            }
+        }
 
-        info!("NewData"); // Everything between NewData and EndData gets saved to a csv to be trained
-        for input in inputs {
-            Timer::after_nanos(10).await;
-            info!("{}", input);
+        // Only the autonomous mode predicts, and acts on the predictions
+        if modes.mode() != Mode::Autonomous {
+            continue;
         }
-        info!("EndData\n");
+
+        // Normalize each channel of the input, see devil_ml_model::normalize. A z-score normalization uses the
+        // statistics from the calibration. The model says which normalization it was trained with.
+        let normalization = match &calibration {
+            Some(calibration) => calibration.normalization(inferer.normalization()),
+            None => inferer.normalization(),
+        };
+        let mut inputs = raw_inputs;
+        normalization.normalize(&mut inputs);
+
+        info!("after inputs nomalization");
 
         info!("Outputted inputs");
 
@@ -182,31 +444,89 @@ async fn main(spawner: Spawner) {
         info!("softmaxing");
 
         info!("inference done!");
-        let result = inference
-            .into_data()
-            .as_slice::<f32>() // Convert the inference tensor into a slice of f32's
-            .unwrap()
-            .into_iter()
+        let probabilities: Vec<f32> = inference.into_data().as_slice::<f32>().unwrap().to_vec();
+        let result = probabilities
+            .iter()
             .enumerate() // Add index onto the probability
             .map(|(index, probability)| {
-                let output = devil_ml_model::Output::try_from(index); // the index is which output it is corresponding with
-                let (output, probability) = match output {
-                    Ok(output) => (output, *probability), // Returns the output gesture and the probability
-                    Err(_) => (devil_ml_model::Output::Unknown, *probability), // This should theoretically never happen, but it's good to test
-                };
-                info!("{:?}: {:?}", output, probability); // Log the results
-                (output, probability) // return the results
+                let output = inferer.labels().label(index); // the index is which label it is corresponding with
+                info!("{}: {:?}", output, probability); // Log the results
+                (index, *probability) // return the results
             })
             .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap()) // get the gesture with the highest probability
             .unwrap();
 
-        info!("Predicted gesture: {:?}\n\n\n", result.0); // Log the gesture
-        // match result.0 {
-        //     devil_ml_model::Output::Flex => gestures.thumbs_up(),
-        //     devil_ml_model::Output::Relax => gestures.pinch(),
-        //     devil_ml_model::Output::Unknown => (),
-        // }
+        if stream::enabled(Stream::Predictions) {
+            stream::send(&Message::Prediction {
+                sequence: amplitudes.sequence,
+                label: String::from(inferer.labels().label(result.0)),
+                confidence: result.1,
+                probabilities,
+            });
+        }
+
+        info!(
+            "Predicted gesture: {}\n\n\n",
+            inferer.labels().label(result.0)
+        ); // Log the gesture
+
+        // Never act on a prediction made from a bad signal, the arm stays exactly where it is
+        if !signal_ok {
+            warn!("Not moving, at least one sensor has a bad signal");
+            continue;
+        }
+
+        // When no muscle is being used the prediction is made from resting noise, so it is treated as a certain
+        // rejection class, which releases anything being held
+        let (output, confidence) = match &calibration {
+            Some(calibration)
+                if !calibration.is_active(&amplitudes.activation, thresholds.activation) =>
+            {
+                (0, 1.0)
+            }
+            _ => result,
+        };
+
+        // Only act once a gesture has been predicted confidently for a while, see devil_ml_model::smoothing
+        let Some(decision) = smoother.update(output, confidence, Instant::now().as_millis()) else {
+            continue;
+        };
+        let label = inferer.labels().label(decision);
+
+        // Pick up changes to the action map made over serial
+        if let Some(map) = ACTION_MAP_UPDATE.try_take() {
+            actions.set_map(map);
+        }
 
-        // Add in here the displaying of the gesture at a later date
+        // The labels come from the model, so they are looked up in the action map at runtime
+        if let Some(motion) = actions.update(label) {
+            info!("Moving for {}: {:?}", label, motion);
+            gestures.play(&motion).await;
+
+            grip_poses = None;
+            match motion {
+                // The frames that arrived during a sequence are stale by now
+                Motion::Sequence(_) => while rx_adv_value.try_receive().is_ok() {},
+                Motion::Grip { open, closed } => {
+                    grip.reset();
+                    grip_poses = Some((open, closed));
+                }
+                Motion::Pose(_) => (),
+            }
+        }
+
+        // While a grip is selected, close it as far as the wearer contracts. This needs the calibration to know
+        // how hard they are contracting.
+        // The time since the last frame that got this far, capped so a long pause can't jump the grip
+        let dt = (last_frame.elapsed().as_micros() as f32 / 1_000_000.0).min(0.1);
+        last_frame = Instant::now();
+        if let (Some((open, closed)), Some(calibration)) = (&grip_poses, &calibration) {
+            let channel = grip.channel().min(CHANNELS - 1);
+            let closure = grip.update(
+                calibration.level(channel, amplitudes.activation[channel]),
+                dt,
+            );
+            gestures.set_pose(&open.lerp(closed, closure));
+        }
     }
 }
@@ -0,0 +1,155 @@
+// The operating mode of the arm, one of
+// - idle: the sensors are read but nothing is sent or predicted, and the servos are off
+// - stream: the streams are sent for recording data, without predicting or moving
+// - calibrate: the calibration is run, then the arm goes back to the mode it was in before
+// - manual: the servos are only moved by the servo and gesture commands, for fitting the arm
+// - autonomous: the servos follow the predictions of the model, this is the mode the arm starts in
+//
+// Any mode can be changed to any other. Leaving autonomous or manual parks the arm at its rest pose, the servos
+// are then turned off in idle, stream and calibrate, and back on in manual and autonomous. The mode is changed with
+// the mode command, or with a button when built with the mode-button feature. The main loop does the actual
+// changing, with ModeMachine keeping track of where it is.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use log::*;
+use portable_atomic::{AtomicU8, Ordering};
+
+pub use devil_protocol::Mode;
+
+use crate::action::Pose;
+use crate::calibration::CALIBRATION_CANCEL;
+use crate::gesture::Gestures;
+use crate::servo::Joint;
+
+// How long the arm is given to get to its rest pose before the servos are turned off
+const PARK_MS: u32 = 1000;
+
+// Set when the mode should change, the main loop picks this up before the next frame
+pub static MODE_REQUEST: Signal<CriticalSectionRawMutex, Mode> = Signal::new();
+
+// The mode the main loop is in, as an index into Mode::ALL
+static CURRENT: AtomicU8 = AtomicU8::new(Mode::Autonomous as u8);
+
+/// The mode the arm is in right now
+pub fn current() -> Mode {
+    Mode::ALL[CURRENT.load(Ordering::Relaxed) as usize]
+}
+
+/// Keeps track of the mode, and which mode to go back to after calibrating
+pub struct ModeMachine {
+    mode: Mode,
+    previous: Mode,
+}
+
+impl ModeMachine {
+    pub fn new() -> Self {
+        let machine = Self {
+            mode: Mode::Autonomous,
+            previous: Mode::Autonomous,
+        };
+        machine.publish();
+        machine
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Change to the requested mode, returns the mode being left, or None if already in that mode
+    pub fn change(&mut self, to: Mode) -> Option<Mode> {
+        if to == self.mode {
+            return None;
+        }
+
+        let from = self.mode;
+        info!("Mode: {} -> {}", from.name(), to.name());
+        self.previous = from;
+        self.mode = to;
+        self.publish();
+        Some(from)
+    }
+
+    /// Go back to the mode before calibrating, returns it
+    pub fn finish_calibration(&mut self) -> Mode {
+        let to = self.previous;
+        self.change(to);
+        to
+    }
+
+    fn publish(&self) {
+        CURRENT.store(self.mode as u8, Ordering::Relaxed);
+    }
+}
+
+impl Default for ModeMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether the streams are sent in the mode
+pub fn streams(mode: Mode) -> bool {
+    matches!(mode, Mode::Stream | Mode::Manual | Mode::Autonomous)
+}
+
+/// What has to be done when leaving a mode
+pub async fn exit<T: Joint, F: Joint, A: Joint>(
+    mode: Mode,
+    gestures: &mut Gestures<T, F, A>,
+    rest: &Pose,
+) {
+    if matches!(mode, Mode::Manual | Mode::Autonomous) {
+        // Never leave the arm holding a pose that nothing is in charge of anymore
+        gestures.transition(rest, PARK_MS);
+        Timer::after(Duration::from_millis(PARK_MS.into())).await;
+    }
+}
+
+/// What has to be done when entering a mode
+pub fn enter<T: Joint, F: Joint, A: Joint>(mode: Mode, gestures: &mut Gestures<T, F, A>) {
+    match mode {
+        Mode::Idle | Mode::Stream | Mode::Calibrate => gestures.stop(),
+        Mode::Manual | Mode::Autonomous => gestures.start(),
+    }
+}
+
+/// Handle a mode command from serial, `mode` returns the current mode and `mode <name>` changes it
+pub fn handle_command(args: &str) -> Result<Mode, ()> {
+    if args.is_empty() {
+        return Ok(current());
+    }
+
+    let mode = Mode::from_name(args).ok_or(())?;
+    // Calibrating holds up the main loop, so it has to be stopped for the change to happen
+    if current() == Mode::Calibrate && mode != Mode::Calibrate {
+        CALIBRATION_CANCEL.signal(());
+    }
+    MODE_REQUEST.signal(mode);
+    Ok(mode)
+}
+
+// Toggles between idle and autonomous each time the button is pressed, so the arm can be stopped without a
+// computer. The button is between PIN_15 and ground.
+#[cfg(feature = "mode-button")]
+#[embassy_executor::task]
+pub async fn mode_button(mut button: embassy_rp::gpio::Input<'static>) {
+    loop {
+        button.wait_for_falling_edge().await;
+
+        // Ignore the contacts bouncing
+        Timer::after(Duration::from_millis(20)).await;
+        if button.is_high() {
+            continue;
+        }
+
+        let next = if current() == Mode::Autonomous {
+            Mode::Idle
+        } else {
+            Mode::Autonomous
+        };
+        MODE_REQUEST.signal(next);
+        button.wait_for_high().await;
+    }
+}
@@ -0,0 +1,208 @@
+// The MODEL region of the flash (see memory.x), which holds a model uploaded over USB. This means a retrained
+// model can be put on the arm without rebuilding and reflashing the firmware.
+//
+// Layout: MAGIC (u32), length (u32), crc32 of the model (u32), 0 (u32), then the model itself. The header is only
+// written once the whole model has been uploaded and its crc checked, so a half finished upload is never used.
+//
+// An upload over serial looks like:
+// - `model begin <length> <crc32 as hex>` erases the region
+// - `model data <offset> <hex>` writes a chunk of the model, replies with `model ack <next offset>`
+// - `model end` checks the crc of what was written, and writes the header, replies with `model done`
+// `model erase` removes the uploaded model, so the one built into the firmware is used again.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use devil_ml_model::{LoadError, ModelHeader};
+use embassy_rp::flash::ERASE_SIZE;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use log::*;
+
+use crate::config::CONFIG;
+use crate::storage::{DevilFlash, CONFIG_REGION_LEN, FLASH_SIZE};
+
+// The size of the MODEL region, this has to match memory.x
+const REGION_LEN: usize = 64 * 1024;
+
+// The MODEL region sits right before the CONFIG region
+const REGION_OFFSET: u32 = (FLASH_SIZE - CONFIG_REGION_LEN - REGION_LEN) as u32;
+
+// Marks the region as holding a complete model
+const MAGIC: u32 = 0xDE71_30D1;
+
+const HEADER_LEN: usize = 16;
+
+// The largest model that fits in the region
+pub const MAX_MODEL_LEN: usize = REGION_LEN - HEADER_LEN;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug)]
+pub enum UploadError {
+    Flash(embassy_rp::flash::Error),
+    // The store, which owns the flash, hasn't been set up yet
+    NotReady,
+    // The model is larger than MAX_MODEL_LEN
+    TooLong,
+    // A chunk arrived without a `model begin`
+    NotStarted,
+    // A chunk arrived for a different offset than expected, holds the expected offset
+    WrongOffset(usize),
+    // The upload ended before the whole model was sent, holds the number of bytes received
+    Incomplete(usize),
+    // The crc of what was written doesn't match the one given at the start, holds the crc of what was written
+    BadCrc(u32),
+    // The model was uploaded fine, but can't be used with this firmware
+    BadModel(LoadError),
+    BadArgs,
+}
+
+impl From<embassy_rp::flash::Error> for UploadError {
+    fn from(err: embassy_rp::flash::Error) -> Self {
+        Self::Flash(err)
+    }
+}
+
+// An upload in progress
+struct Upload {
+    len: usize,
+    crc: u32,
+    received: usize,
+}
+
+static UPLOAD: Mutex<CriticalSectionRawMutex, Option<Upload>> = Mutex::new(None);
+
+/// Read the uploaded model, returns None if there is no complete model in the region
+pub fn load(flash: &mut DevilFlash) -> Option<Vec<u8>> {
+    let mut header = [0u8; HEADER_LEN];
+    if let Err(err) = flash.blocking_read(REGION_OFFSET, &mut header) {
+        error!("Failed to read the model partition: {:?}", err);
+        return None;
+    }
+
+    let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let crc = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    if magic != MAGIC || len > MAX_MODEL_LEN {
+        return None;
+    }
+
+    let mut model = vec![0u8; len];
+    flash
+        .blocking_read(REGION_OFFSET + HEADER_LEN as u32, &mut model)
+        .ok()?;
+    if CRC.checksum(&model) != crc {
+        warn!("The uploaded model is corrupt, ignoring it");
+        return None;
+    }
+
+    Some(model)
+}
+
+// Erase every sector the header and a model of len bytes take up
+fn erase(flash: &mut DevilFlash, len: usize) -> Result<(), UploadError> {
+    let end = (HEADER_LEN + len).div_ceil(ERASE_SIZE) * ERASE_SIZE;
+    flash.blocking_erase(REGION_OFFSET, REGION_OFFSET + end as u32)?;
+    Ok(())
+}
+
+fn parse_hex(hex: &str, buf: &mut [u8]) -> Option<usize> {
+    if hex.len() % 2 != 0 || hex.len() / 2 > buf.len() {
+        return None;
+    }
+    for (byte, pair) in buf.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(hex.len() / 2)
+}
+
+/// Handle a model command from serial, see the top of this file
+pub async fn handle_command(args: &str) -> Result<(), UploadError> {
+    // The ConfigStore owns the flash, so it is borrowed from there
+    let mut store = CONFIG.lock().await;
+    let flash = store.as_mut().ok_or(UploadError::NotReady)?.flash();
+    let mut upload = UPLOAD.lock().await;
+
+    let mut args = args.split_whitespace();
+    match args.next().unwrap_or("") {
+        "begin" => {
+            let len: usize = args
+                .next()
+                .and_then(|len| len.parse().ok())
+                .ok_or(UploadError::BadArgs)?;
+            let crc = args
+                .next()
+                .and_then(|crc| u32::from_str_radix(crc, 16).ok())
+                .ok_or(UploadError::BadArgs)?;
+            if len > MAX_MODEL_LEN {
+                return Err(UploadError::TooLong);
+            }
+
+            *upload = None;
+            erase(flash, len)?;
+            *upload = Some(Upload {
+                len,
+                crc,
+                received: 0,
+            });
+            info!("model ack 0");
+        }
+        "data" => {
+            let upload = upload.as_mut().ok_or(UploadError::NotStarted)?;
+            let offset: usize = args
+                .next()
+                .and_then(|offset| offset.parse().ok())
+                .ok_or(UploadError::BadArgs)?;
+            if offset != upload.received {
+                return Err(UploadError::WrongOffset(upload.received));
+            }
+
+            let mut chunk = [0u8; 64];
+            let len =
+                parse_hex(args.next().unwrap_or(""), &mut chunk).ok_or(UploadError::BadArgs)?;
+            if offset + len > upload.len {
+                return Err(UploadError::TooLong);
+            }
+
+            flash.blocking_write(REGION_OFFSET + (HEADER_LEN + offset) as u32, &chunk[..len])?;
+            upload.received += len;
+            info!("model ack {}", upload.received);
+        }
+        "end" => {
+            let finished = upload.take().ok_or(UploadError::NotStarted)?;
+            if finished.received != finished.len {
+                return Err(UploadError::Incomplete(finished.received));
+            }
+
+            // Check what actually ended up in the flash, not what was sent
+            let mut model = vec![0u8; finished.len];
+            flash.blocking_read(REGION_OFFSET + HEADER_LEN as u32, &mut model)?;
+            let crc = CRC.checksum(&model);
+            if crc != finished.crc {
+                return Err(UploadError::BadCrc(crc));
+            }
+
+            // Refuse models which wouldn't load anyway, rather than finding out after a reboot
+            let (header, _) = ModelHeader::decode(&model).map_err(UploadError::BadModel)?;
+            header.validate().map_err(UploadError::BadModel)?;
+
+            let mut partition_header = [0u8; HEADER_LEN];
+            partition_header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+            partition_header[4..8].copy_from_slice(&(finished.len as u32).to_le_bytes());
+            partition_header[8..12].copy_from_slice(&crc.to_le_bytes());
+            flash.blocking_write(REGION_OFFSET, &partition_header)?;
+            info!("model done");
+            info!("Reboot to use the new model");
+        }
+        "erase" => {
+            *upload = None;
+            erase(flash, 0)?;
+            info!("Uploaded model erased, reboot to use the built in model");
+        }
+        _ => return Err(UploadError::BadArgs),
+    }
+
+    Ok(())
+}
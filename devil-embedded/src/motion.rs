@@ -0,0 +1,198 @@
+// Smooth servo motion. Instead of jumping straight to an angle, each servo is moved by its own MotionPlanner,
+// which eases it to the target over a duration (or at a maximum speed), updating the pulse width once every
+// refresh of the servo. A new target cancels the move in progress, and the new move starts from wherever the
+// servo got to.
+//
+// The planners run in their own tasks, and are sent commands through a channel. PlannedJoint is the sending
+// side, it implements Joint so Gestures can use it in place of a servo.
+
+use easer::functions::{Cubic, Easing, Linear, Quad, Sine};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::{Channel, Receiver, Sender};
+use embassy_time::{Duration, Timer};
+use log::*;
+use num_traits::Float;
+
+use crate::config::Record;
+use crate::servo::{Joint, REFRESH_INTERVAL};
+
+pub use devil_arm::motion::{Curve, MotionConfig, Timing};
+
+// How often a planner updates its servo, once every servo refresh
+const STEP: Duration = Duration::from_micros(REFRESH_INTERVAL);
+
+pub type MotionChannel = Channel<CriticalSectionRawMutex, Command, 4>;
+
+/// The commands of the thumb, fingers and arm planners, in that order
+pub static MOTION_COMMANDS: [MotionChannel; 3] = [Channel::new(), Channel::new(), Channel::new()];
+
+// The position at time t into a move from start, which changes by change over duration
+fn ease(curve: Curve, t: f32, start: f32, change: f32, duration: f32) -> f32 {
+    match curve {
+        Curve::Linear => Linear::ease_in_out(t, start, change, duration),
+        Curve::Quad => Quad::ease_in_out(t, start, change, duration),
+        Curve::Cubic => Cubic::ease_in_out(t, start, change, duration),
+        Curve::Sine => Sine::ease_in_out(t, start, change, duration),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Move {
+    pub degree: f32,
+    pub timing: Timing,
+    pub curve: Curve,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Start,
+    Stop,
+    Move(Move),
+}
+
+impl Record for MotionConfig {
+    const KEY: u16 = 7;
+    const NAME: &'static str = "motion";
+    const VERSION: u8 = 1;
+
+    fn is_valid(&self) -> bool {
+        MotionConfig::is_valid(self)
+    }
+}
+
+/// Moves a single servo, see the top of this file
+pub struct MotionPlanner<J: Joint> {
+    joint: J,
+    // Where the servo is, in degrees
+    position: f32,
+}
+
+impl<J: Joint> MotionPlanner<J> {
+    /// position is where the servo is to begin with
+    pub fn new(joint: J, position: f32) -> Self {
+        Self { joint, position }
+    }
+
+    /// Follow the commands forever
+    pub async fn run(
+        mut self,
+        commands: Receiver<'static, CriticalSectionRawMutex, Command, 4>,
+    ) -> ! {
+        let mut next = None;
+        loop {
+            let command = match next.take() {
+                Some(command) => command,
+                None => commands.receive().await,
+            };
+
+            match command {
+                Command::Start => self.joint.start(),
+                Command::Stop => self.joint.stop(),
+                Command::Move(target) => next = self.move_to(target, &commands).await,
+            }
+        }
+    }
+
+    // Ease the servo to the target. Returns the command that cancelled the move, if one arrived before the end.
+    async fn move_to(
+        &mut self,
+        target: Move,
+        commands: &Receiver<'static, CriticalSectionRawMutex, Command, 4>,
+    ) -> Option<Command> {
+        let start = self.position;
+        let change = target.degree.max(0.0) - start;
+        let duration = target.timing.duration(change, target.curve);
+
+        let step = STEP.as_micros() as f32 / 1_000_000.0;
+        let steps = (duration / step).ceil().max(1.0) as u32;
+        for index in 1..=steps {
+            self.position = if index == steps {
+                start + change
+            } else {
+                ease(target.curve, index as f32 * step, start, change, duration)
+            };
+            self.joint.rotate(self.position);
+
+            if index < steps {
+                if let Either::Second(command) =
+                    select(Timer::after(STEP), commands.receive()).await
+                {
+                    return Some(command);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// A servo moved by a MotionPlanner. Every rotate eases the servo there with the MotionConfig.
+pub struct PlannedJoint {
+    commands: Sender<'static, CriticalSectionRawMutex, Command, 4>,
+    max_degree_rotation: f32,
+    config: MotionConfig,
+    // Where the last move sent was going, so how far the next one has to go is known
+    target: f32,
+}
+
+impl PlannedJoint {
+    /// position is where the planner starts the servo, as given to MotionPlanner::new
+    pub fn new(
+        commands: &'static MotionChannel,
+        max_degree_rotation: f32,
+        config: MotionConfig,
+        position: f32,
+    ) -> Self {
+        Self {
+            commands: commands.sender(),
+            max_degree_rotation,
+            config,
+            target: position,
+        }
+    }
+
+    /// Move to degree with a timing and curve of its own
+    pub fn move_to(&mut self, degree: f32, timing: Timing, curve: Curve) {
+        self.target = degree.max(0.0);
+        self.send(Command::Move(Move {
+            degree,
+            timing,
+            curve,
+        }));
+    }
+
+    fn send(&mut self, command: Command) {
+        if self.commands.try_send(command).is_err() {
+            warn!("The motion planner is behind, dropping {:?}", command);
+        }
+    }
+}
+
+impl Joint for PlannedJoint {
+    fn start(&mut self) {
+        self.send(Command::Start);
+    }
+
+    fn stop(&mut self) {
+        self.send(Command::Stop);
+    }
+
+    fn rotate(&mut self, degree: f32) {
+        self.move_to(degree, self.config.timing, self.config.curve);
+    }
+
+    fn max_degree_rotation(&self) -> f32 {
+        self.max_degree_rotation
+    }
+
+    fn rotate_over(&mut self, degree: f32, duration_ms: u32) {
+        self.move_to(degree, Timing::Duration(duration_ms), self.config.curve);
+    }
+
+    // The planner starts from wherever the last move got to, this assumes it got all the way there
+    fn move_ms(&self, degree: f32) -> u32 {
+        let distance = degree.max(0.0) - self.target;
+        (self.config.timing.duration(distance, self.config.curve) * 1000.0).ceil() as u32
+    }
+}
@@ -0,0 +1,129 @@
+// Labelled recordings made on the arm. The host sends a Command::Record asking for a number of frames of a label,
+// and the main loop sends exactly that many frames of features as Message::Recorded, each one carrying the label,
+// then a Message::RecordingDone. Because the label is attached here, and not by the host guessing which frames
+// went with which prompt, the labels line up exactly with what the wearer was doing.
+//
+// Recordings are only made in stream mode, where the arm holds still. Only frames computed entirely from samples
+// read after the request are recorded, and a frame that doesn't fit in the data pipe doesn't count, so every
+// recording has exactly the frames asked for with no gaps. The frames skipped along the way are counted in
+// dropped.
+
+use alloc::string::String;
+
+use devil_dsp::WINDOW_LEN;
+use devil_protocol::{Message, Outcome};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use log::*;
+
+use crate::sensor::HOP;
+use crate::stream;
+
+// How many times the end of a recording is tried, 10ms apart
+const FINISH_TRIES: u32 = 50;
+
+// Set by commands.rs when the host asks to start or stop a recording, the main loop picks it up
+pub static RECORD_REQUEST: Signal<CriticalSectionRawMutex, RecordRequest> = Signal::new();
+
+pub enum RecordRequest {
+    Start {
+        recording: u32,
+        label: String,
+        frames: u32,
+    },
+    Stop,
+}
+
+/// A recording in progress
+pub struct Recording {
+    recording: u32,
+    label: String,
+    frames: u32,
+    recorded: u32,
+    dropped: u32,
+    // The frames still to be skipped because their window started before the request
+    skip: u32,
+    last_sequence: Option<u32>,
+}
+
+impl Recording {
+    /// Start a recording, the frames already waiting in the channel should be thrown away first
+    pub fn new(recording: u32, label: String, frames: u32) -> Self {
+        info!("Recording {} frames of {} ({})", frames, label, recording);
+        Self {
+            recording,
+            label,
+            frames,
+            recorded: 0,
+            dropped: 0,
+            // The first frames still have samples from before the request in their window
+            skip: (WINDOW_LEN / HOP) as u32,
+            last_sequence: None,
+        }
+    }
+
+    /// Tell the host a recording can't be made right now
+    pub fn refuse(recording: u32) {
+        warn!("Not recording {}, only recording in stream mode", recording);
+        stream::send(&Message::RecordingDone {
+            recording,
+            recorded: 0,
+            dropped: 0,
+            outcome: Outcome::Refused,
+        });
+    }
+
+    /// Record the next frame, returns true once every frame has been recorded
+    pub fn record(&mut self, sequence: u32, features: &[f32]) -> bool {
+        // Frames the sensors computed that never got here were dropped before the main loop
+        if let Some(last) = self.last_sequence {
+            self.dropped += sequence.wrapping_sub(last).saturating_sub(1);
+        }
+        self.last_sequence = Some(sequence);
+
+        if self.skip > 0 {
+            self.skip -= 1;
+            return false;
+        }
+        if self.recorded >= self.frames {
+            return true;
+        }
+
+        let sent = stream::send(&Message::Recorded {
+            recording: self.recording,
+            label: self.label.clone(),
+            index: self.recorded,
+            sequence,
+            features: features.to_vec(),
+        });
+        if sent {
+            self.recorded += 1;
+        } else {
+            self.dropped += 1;
+        }
+        self.recorded >= self.frames
+    }
+
+    /// Let the host know the recording is over
+    pub async fn finish(self, outcome: Outcome) {
+        info!(
+            "Recording {} {:?}: {} of {} frames of {}, {} dropped",
+            self.recording, outcome, self.recorded, self.frames, self.label, self.dropped
+        );
+        // This has to get to the host or it would wait forever, so it is retried while the pipe empties
+        let done = Message::RecordingDone {
+            recording: self.recording,
+            recorded: self.recorded,
+            dropped: self.dropped,
+            outcome,
+        };
+        for _ in 0..FINISH_TRIES {
+            if stream::send(&done) {
+                return;
+            }
+            Timer::after(Duration::from_millis(10)).await;
+        }
+        error!("Failed to send the end of recording {}", self.recording);
+    }
+}
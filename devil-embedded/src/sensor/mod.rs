@@ -0,0 +1,154 @@
+use alloc::vec::Vec;
+use devil_protocol::{Message, Stream};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::{Channel, Sender};
+use embassy_time::{Duration, Instant};
+use log::{info, warn};
+use portable_atomic::{AtomicU32, Ordering};
+
+use devil_dsp::{ChannelQuality, FeatureExtractor, Frame, QualityLimits, CHANNELS, SAMPLE_RATE_HZ};
+
+#[cfg(feature = "ads1299")]
+use embassy_rp::{gpio, peripherals::SPI0, spi, spi::Spi};
+#[cfg(feature = "ads1299")]
+use embassy_time::Delay;
+#[cfg(feature = "ads1299")]
+use embedded_hal_bus::spi::ExclusiveDevice;
+
+use crate::{mode, stream};
+use {defmt_rtt as _, panic_probe as _};
+
+#[cfg(not(feature = "ads1299"))]
+mod onchip;
+
+// The ADS1299 driver lives in devil-arm, where it is tested against a mock SPI device
+#[cfg(feature = "ads1299")]
+pub use devil_arm::sensor::Ads1299;
+pub use devil_arm::sensor::SensorSource;
+#[cfg(not(feature = "ads1299"))]
+pub use onchip::OnChipAdc;
+
+// This is the frame of features we will send over the channel to the main process, along with the quality
+// and activation of each sensor over the same window
+pub struct Amplitudes {
+    /// Counts up by one with every frame, including the ones that get dropped
+    pub sequence: u32,
+    pub features: Frame,
+    pub quality: [ChannelQuality; CHANNELS],
+    pub activation: [f32; CHANNELS],
+}
+
+// The default limits each sensor's signal has to stay within, see devil_dsp::quality and config::Thresholds
+#[cfg(not(feature = "ads1299"))]
+pub const QUALITY_LIMITS: QualityLimits = QualityLimits::new();
+// The ADS1299 samples use the whole u16 range
+#[cfg(feature = "ads1299")]
+pub const QUALITY_LIMITS: QualityLimits = QualityLimits {
+    sample_max: u16::MAX,
+    ..QualityLimits::new()
+};
+
+// The number of new samples between each frame, the window is always devil_dsp::WINDOW_LEN samples long.
+// A smaller hop means more decisions per second, without changing the number of inputs to the model.
+pub const HOP: usize = 16;
+
+// How often the achieved sample rate is measured and reported
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// The channel which we send and recieve data through
+pub static CHANNEL_AMPLITUDES: Channel<ThreadModeRawMutex, Amplitudes, 64> = Channel::new();
+
+// The sample rate achieved over the last REPORT_INTERVAL, in Hz
+pub static ACHIEVED_SAMPLE_RATE: AtomicU32 = AtomicU32::new(0);
+
+// The number of frames thrown away because the main process didn't keep up with the sensors
+pub static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+
+// The source the sensor task reads from, tasks can't be generic so it is picked with the ads1299 feature
+#[cfg(not(feature = "ads1299"))]
+pub type SensorBackend = OnChipAdc;
+#[cfg(feature = "ads1299")]
+pub type SensorBackend = Ads1299<
+    ExclusiveDevice<Spi<'static, SPI0, spi::Async>, gpio::Output<'static>, Delay>,
+    gpio::Input<'static>,
+>;
+
+// This is technically a process, which allows us to use Async
+#[embassy_executor::task]
+pub async fn read_adc_value(
+    mut source: SensorBackend,
+    quality_limits: QualityLimits,
+    tx_value: Sender<'static, ThreadModeRawMutex, Amplitudes, 64>,
+) {
+    // Filters the samples and turns them into features, see devil-dsp
+    let mut extractor = FeatureExtractor::new(HOP);
+
+    let mut report_start = Instant::now();
+    let mut samples: u64 = 0;
+    let mut sequence: u32 = 0;
+
+    // The raw samples waiting to be streamed to the host, they are sent HOP at a time. sample_sequence counts
+    // every sample read.
+    let mut raw_samples: Vec<u16> = Vec::with_capacity(HOP * CHANNELS);
+    let mut sample_sequence: u32 = 0;
+
+    loop {
+        // Read a sample from each sensor, the source makes sure this happens at SAMPLE_RATE_HZ
+        let sample = match source.read().await {
+            Ok(sample) => sample,
+            Err(err) => {
+                warn!("Failed to read the sensors: {:?}", err);
+                continue;
+            }
+        };
+        samples += 1;
+        sample_sequence = sample_sequence.wrapping_add(1);
+
+        if stream::enabled(Stream::Samples) && mode::streams(mode::current()) {
+            raw_samples.extend_from_slice(&sample);
+            if raw_samples.len() >= HOP * CHANNELS {
+                stream::send(&Message::Samples {
+                    sequence: sample_sequence.wrapping_sub(HOP as u32),
+                    channels: CHANNELS as u8,
+                    samples: core::mem::take(&mut raw_samples),
+                });
+                raw_samples.reserve(HOP * CHANNELS);
+            }
+        } else {
+            raw_samples.clear();
+        }
+
+        // Every HOP samples, once the first window is full, a frame of features comes out
+        if let Some(features) = extractor.push(sample) {
+            let amplitudes = Amplitudes {
+                sequence,
+                features,
+                quality: extractor.quality(&quality_limits),
+                activation: extractor.activation(),
+            };
+
+            // send amplitudes to main thread, waiting here would stall the sampling, so drop the frame instead
+            if tx_value.try_send(amplitudes).is_err() {
+                OVERRUNS.fetch_add(1, Ordering::Relaxed);
+            }
+            sequence = sequence.wrapping_add(1);
+        }
+
+        // Measure how many samples were actually taken, if the reads take longer than a sample period this
+        // drops below SAMPLE_RATE_HZ
+        let elapsed = report_start.elapsed();
+        if elapsed >= REPORT_INTERVAL {
+            let rate = (samples * 1_000_000 / elapsed.as_micros()) as u32;
+            ACHIEVED_SAMPLE_RATE.store(rate, Ordering::Relaxed);
+            info!(
+                "Sampling at {} Hz (expected {} Hz), {} overruns",
+                rate,
+                SAMPLE_RATE_HZ,
+                OVERRUNS.load(Ordering::Relaxed)
+            );
+
+            report_start = Instant::now();
+            samples = 0;
+        }
+    }
+}
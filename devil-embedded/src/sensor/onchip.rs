@@ -0,0 +1,39 @@
+use embassy_rp::adc::{self, Adc, Async};
+use embassy_time::{Duration, Ticker};
+
+use devil_dsp::{Sample, CHANNELS, SAMPLE_RATE_HZ};
+
+use super::SensorSource;
+
+/// The RP2040's built in 12-bit ADC, with one pin per sensor. It has no clock of its own, so a ticker is used
+/// to take a sample at exactly SAMPLE_RATE_HZ no matter how long the reads take.
+pub struct OnChipAdc {
+    adc: Adc<'static, Async>,
+    channels: [adc::Channel<'static>; CHANNELS],
+    ticker: Ticker,
+}
+
+impl OnChipAdc {
+    pub fn new(adc: Adc<'static, Async>, channels: [adc::Channel<'static>; CHANNELS]) -> Self {
+        Self {
+            adc,
+            channels,
+            ticker: Ticker::every(Duration::from_hz(SAMPLE_RATE_HZ)),
+        }
+    }
+}
+
+impl SensorSource for OnChipAdc {
+    type Error = adc::Error;
+
+    async fn read(&mut self) -> Result<Sample, Self::Error> {
+        self.ticker.next().await;
+
+        let mut sample: Sample = [0; CHANNELS];
+        for (value, channel) in sample.iter_mut().zip(self.channels.iter_mut()) {
+            *value = self.adc.read(channel).await?;
+        }
+
+        Ok(sample)
+    }
+}
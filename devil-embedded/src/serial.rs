@@ -1,14 +1,18 @@
 use core::str;
 
-use embassy_futures::join::join;
+use devil_protocol::{Decoder, Message};
+use embassy_futures::join::join4;
 use embassy_rp::peripherals::USB;
 use embassy_rp::rom_data::reset_to_usb_boot;
 use embassy_rp::usb::Driver;
-use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, Receiver, Sender, State};
 
 use embassy_usb::{Builder, Config};
 use embassy_usb_logger::{with_class, ReceiverHandler};
 
+use crate::commands;
+use crate::stream::DATA_PIPE;
+
 // Create a new command handler
 struct Handler;
 
@@ -21,10 +25,9 @@ impl ReceiverHandler for Handler {
             // this will automatically put the pico into boot mode.
             if data == "q" || data == "elf2uf2-term" {
                 reset_to_usb_boot(0, 0); // Restart the chip
-            } else if data.eq_ignore_ascii_case("hello") {
-                log::info!("World!");
             } else {
-                log::info!("Recieved: {:?}", data);
+                // Everything else is one of the commands, the reply is logged, see commands.rs
+                commands::run(data).await;
             }
         }
     }
@@ -34,6 +37,44 @@ impl ReceiverHandler for Handler {
     }
 }
 
+// Send the frames queued by stream.rs to the host, one packet at a time
+async fn send_data(sender: &mut Sender<'_, Driver<'static, USB>>) {
+    let mut packet = [0u8; 64];
+    loop {
+        sender.wait_connection().await;
+        loop {
+            let len = DATA_PIPE.read(&mut packet).await;
+            if sender.write_packet(&packet[..len]).await.is_err() {
+                break; // Disconnected, the frame is lost but the next one starts after a zero anyway
+            }
+        }
+    }
+}
+
+// Decode the frames the host sends, and run the commands in them
+async fn receive_commands(receiver: &mut Receiver<'_, Driver<'static, USB>>) {
+    let mut packet = [0u8; 64];
+    let mut decoder = Decoder::new();
+    loop {
+        receiver.wait_connection().await;
+        loop {
+            let Ok(len) = receiver.read_packet(&mut packet).await else {
+                break; // Disconnected
+            };
+            for &byte in &packet[..len] {
+                match decoder.push(byte) {
+                    Some(Ok(Message::Command(command))) => commands::handle(command).await,
+                    Some(Ok(message)) => {
+                        log::warn!("Expected a command from the host, got {:?}", message)
+                    }
+                    Some(Err(err)) => log::warn!("Dropped a frame from the host: {:?}", err),
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
 #[embassy_executor::task]
 pub async fn usb_task(driver: Driver<'static, USB>) {
     // Create embassy-usb Config
@@ -58,6 +99,7 @@ pub async fn usb_task(driver: Driver<'static, USB>) {
     let mut control_buf = [0; 64];
 
     let mut state = State::new();
+    let mut data_state = State::new();
 
     let mut builder = Builder::new(
         driver,
@@ -68,14 +110,20 @@ pub async fn usb_task(driver: Driver<'static, USB>) {
         &mut control_buf,
     );
 
-    // Create classes on the builder.
+    // Create classes on the builder, the device is composite with two serial ports. The first is for people, it
+    // carries the log and the text commands. The second is for programs, it carries the binary protocol of
+    // devil-protocol both ways, so a host that only wants the data never sees a log line.
     let class = CdcAcmClass::new(&mut builder, &mut state, 64);
+    let data_class = CdcAcmClass::new(&mut builder, &mut data_state, 64);
+    let (mut data_sender, mut data_receiver) = data_class.split();
 
     let mut device = builder.build();
 
-    join(
+    join4(
         device.run(),
         with_class!(1024, log::LevelFilter::Info, class, Handler),
+        send_data(&mut data_sender),
+        receive_commands(&mut data_receiver),
     )
     .await;
 }
@@ -1,20 +1,21 @@
 #![allow(unused)]
 use core::time::Duration;
 
+pub use devil_arm::servo::Joint;
 use embassy_rp::pio::Instance;
 use embassy_rp::pio_programs::pwm::PioPwm;
 
+use crate::config::ServoLimits;
+
 const DEFAULT_MIN_PULSE_WIDTH: u64 = 1000; // uncalibrated default, the shortest duty cycle sent to a servo
 const DEFAULT_MAX_PULSE_WIDTH: u64 = 2000; // uncalibrated default, the longest duty cycle sent to a servo
-const DEFAULT_MAX_DEGREE_ROTATION: u64 = 160; // 160 degrees is typical
-const REFRESH_INTERVAL: u64 = 20000; // The period of each cycle
+const DEFAULT_MAX_DEGREE_ROTATION: f32 = 160.0; // 160 degrees is typical
+pub const REFRESH_INTERVAL: u64 = 20000; // The period of each cycle, in microseconds
 
 pub struct ServoBuilder<'d, T: Instance, const SM: usize> {
     pwm: PioPwm<'d, T, SM>,
     period: Duration,
-    min_pulse_width: Duration,
-    max_pulse_width: Duration,
-    max_degree_rotation: u64,
+    limits: ServoLimits,
 }
 
 impl<'d, T: Instance, const SM: usize> ServoBuilder<'d, T, SM> {
@@ -22,9 +23,15 @@ impl<'d, T: Instance, const SM: usize> ServoBuilder<'d, T, SM> {
         Self {
             pwm,
             period: Duration::from_micros(REFRESH_INTERVAL),
-            min_pulse_width: Duration::from_micros(DEFAULT_MIN_PULSE_WIDTH),
-            max_pulse_width: Duration::from_micros(DEFAULT_MAX_PULSE_WIDTH),
-            max_degree_rotation: DEFAULT_MAX_DEGREE_ROTATION,
+            limits: ServoLimits {
+                max_degree_rotation: DEFAULT_MAX_DEGREE_ROTATION,
+                min_pulse_width_us: DEFAULT_MIN_PULSE_WIDTH,
+                max_pulse_width_us: DEFAULT_MAX_PULSE_WIDTH,
+                inverted: false,
+                neutral_offset: 0.0,
+                min_angle: 0.0,
+                max_angle: DEFAULT_MAX_DEGREE_ROTATION,
+            },
         }
     }
 
@@ -34,17 +41,43 @@ impl<'d, T: Instance, const SM: usize> ServoBuilder<'d, T, SM> {
     }
 
     pub fn set_min_pulse_width(mut self, duration: Duration) -> Self {
-        self.min_pulse_width = duration;
+        self.limits.min_pulse_width_us = duration.as_micros() as u64;
         self
     }
 
     pub fn set_max_pulse_width(mut self, duration: Duration) -> Self {
-        self.max_pulse_width = duration;
+        self.limits.max_pulse_width_us = duration.as_micros() as u64;
+        self
+    }
+
+    // Also moves the max angle out to the whole rotation
+    pub fn set_max_degree_rotation(mut self, degree: f32) -> Self {
+        self.limits.max_degree_rotation = degree;
+        self.limits.max_angle = degree;
+        self
+    }
+
+    // Reverse the direction of the servo, instead of swapping the pulse widths
+    pub fn set_inverted(mut self, inverted: bool) -> Self {
+        self.limits.inverted = inverted;
+        self
+    }
+
+    pub fn set_neutral_offset(mut self, degree: f32) -> Self {
+        self.limits.neutral_offset = degree;
         self
     }
 
-    pub fn set_max_degree_rotation(mut self, degree: u64) -> Self {
-        self.max_degree_rotation = degree;
+    // The angles the servo is kept between
+    pub fn set_angle_limits(mut self, min: f32, max: f32) -> Self {
+        self.limits.min_angle = min;
+        self.limits.max_angle = max;
+        self
+    }
+
+    // Set the whole calibration from the limits stored in the config
+    pub fn set_limits(mut self, limits: ServoLimits) -> Self {
+        self.limits = limits;
         self
     }
 
@@ -52,18 +85,14 @@ impl<'d, T: Instance, const SM: usize> ServoBuilder<'d, T, SM> {
         self.pwm.set_period(self.period);
         Servo {
             pwm: self.pwm,
-            min_pulse_width: self.min_pulse_width,
-            max_pulse_width: self.max_pulse_width,
-            max_degree_rotation: self.max_degree_rotation,
+            limits: self.limits,
         }
     }
 }
 
 pub struct Servo<'d, T: Instance, const SM: usize> {
     pub pwm: PioPwm<'d, T, SM>,
-    pub min_pulse_width: Duration,
-    pub max_pulse_width: Duration,
-    pub max_degree_rotation: u64,
+    pub limits: ServoLimits,
 }
 
 impl<'d, T: Instance, const SM: usize> Servo<'d, T, SM> {
@@ -79,17 +108,25 @@ impl<'d, T: Instance, const SM: usize> Servo<'d, T, SM> {
         self.pwm.write(duration);
     }
 
-    pub fn rotate(&mut self, degree: u64) {
-        let degree_per_nano_second = (self.max_pulse_width.as_nanos() as u64
-            - self.min_pulse_width.as_nanos() as u64)
-            / self.max_degree_rotation;
-        let mut duration = Duration::from_nanos(
-            degree * degree_per_nano_second + self.min_pulse_width.as_nanos() as u64,
-        );
-        if self.max_pulse_width < duration {
-            duration = self.max_pulse_width;
-        }
+    pub fn rotate(&mut self, degree: f32) {
+        self.pwm.write(self.limits.pulse_width(degree));
+    }
+}
 
-        self.pwm.write(duration);
+impl<'d, T: Instance, const SM: usize> Joint for Servo<'d, T, SM> {
+    fn start(&mut self) {
+        Servo::start(self);
+    }
+
+    fn stop(&mut self) {
+        Servo::stop(self);
+    }
+
+    fn rotate(&mut self, degree: f32) {
+        Servo::rotate(self, degree);
+    }
+
+    fn max_degree_rotation(&self) -> f32 {
+        self.limits.max_degree_rotation
     }
 }
@@ -0,0 +1,20 @@
+// Where the ConfigStore (see devil_arm::storage) lives on the Pico, the CONFIG region at the very end of the
+// flash (see memory.x).
+
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+
+pub use devil_arm::storage::{StoreError, MAX_VALUE_LEN};
+
+// The size of the flash on the Pico
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+// The size of the CONFIG region, this has to match memory.x
+pub const CONFIG_REGION_LEN: usize = devil_arm::storage::SECTORS * ERASE_SIZE;
+
+// Where the CONFIG region starts, as an offset from the start of the flash
+pub const REGION_OFFSET: u32 = (FLASH_SIZE - CONFIG_REGION_LEN) as u32;
+
+pub type DevilFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+pub type ConfigStore = devil_arm::storage::ConfigStore<DevilFlash>;
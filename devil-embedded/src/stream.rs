@@ -0,0 +1,92 @@
+// Sends messages to the host in the binary protocol of devil-protocol, over the data interface (see serial.rs).
+// Messages are encoded into frames and queued in DATA_PIPE, which the USB task empties as fast as the host reads.
+// Nothing here ever waits on the host, when the pipe is full the message is dropped, so a host that isn't
+// listening can't stall the sensors or the main loop. The commands the host sends back on the same interface
+// are handled in commands.rs.
+
+use devil_protocol::{Message, Stream, MAX_FRAME_LEN};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_sync::signal::Signal;
+use log::*;
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+
+const DATA_PIPE_LEN: usize = 4096;
+
+// The encoded frames waiting to be sent to the host
+pub static DATA_PIPE: Pipe<CriticalSectionRawMutex, DATA_PIPE_LEN> = Pipe::new();
+
+// Set when the host asks for a Status, the main loop sends one with the next frame
+pub static STATUS_REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// The number of messages dropped because the host wasn't reading them
+pub static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+// Which streams are being sent, features and predictions are on to begin with
+static FEATURES: AtomicBool = AtomicBool::new(true);
+static SAMPLES: AtomicBool = AtomicBool::new(false);
+static PREDICTIONS: AtomicBool = AtomicBool::new(true);
+
+fn flag(stream: Stream) -> &'static AtomicBool {
+    match stream {
+        Stream::Features => &FEATURES,
+        Stream::Samples => &SAMPLES,
+        Stream::Predictions => &PREDICTIONS,
+    }
+}
+
+/// Whether the stream is being sent
+pub fn enabled(stream: Stream) -> bool {
+    flag(stream).load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(stream: Stream, enabled: bool) {
+    flag(stream).store(enabled, Ordering::Relaxed);
+}
+
+/// Queue a message for the host, it is dropped if there isn't room for it. Returns whether it was queued.
+pub fn send(message: &Message) -> bool {
+    let mut frame = [0u8; MAX_FRAME_LEN];
+    let len = match devil_protocol::encode(message, &mut frame) {
+        Ok(len) => len,
+        Err(err) => {
+            warn!("Failed to encode a message: {:?}", err);
+            return false;
+        }
+    };
+
+    // Half a frame would corrupt the next one as well, so only start writing if all of it fits. The pipe only
+    // writes up to where its buffer wraps around, so this can take two writes.
+    if DATA_PIPE.free_capacity() < len {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    let mut written = 0;
+    while written < len {
+        match DATA_PIPE.try_write(&frame[written..len]) {
+            Ok(n) => written += n,
+            Err(_) => break,
+        }
+    }
+    true
+}
+
+/// Handle a stream command, `stream <features|samples|predictions> <on|off>`
+pub fn handle_command(args: &str) -> Result<(), ()> {
+    let mut args = args.split_whitespace();
+    let stream = match args.next() {
+        Some("features") => Stream::Features,
+        Some("samples") => Stream::Samples,
+        Some("predictions") => Stream::Predictions,
+        _ => return Err(()),
+    };
+    let enabled = match args.next() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return Err(()),
+    };
+
+    set_enabled(stream, enabled);
+    info!("Stream {:?} {}", stream, if enabled { "on" } else { "off" });
+    Ok(())
+}
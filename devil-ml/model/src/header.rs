@@ -0,0 +1,228 @@
+// The container a trained model is stored in. The burn record on its own says nothing about what the model
+// expects, so loading a model trained for different inputs or outputs would either panic or quietly give wrong
+// predictions. The header describes the model, and is checked against this build when the model is loaded.
+//
+// Layout: MAGIC (4 bytes), FORMAT_VERSION (u16), length of the header (u32), the header encoded with postcard,
+// then the burn record. All numbers are little endian.
+
+use alloc::vec::Vec;
+
+use burn::record::RecorderError;
+use serde::{Deserialize, Serialize};
+
+use crate::{LabelSet, Normalization, MODEL_INPUTS};
+
+/// Marks the start of a model file
+pub const MAGIC: [u8; 4] = *b"DVLM";
+
+/// Bump this whenever the layout of the container or the header changes
+pub const FORMAT_VERSION: u16 = 2;
+
+// MAGIC, FORMAT_VERSION and the length of the header
+const PREFIX_LEN: usize = 10;
+
+/// Everything needed to know whether a model can be used with this build
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelHeader {
+    /// The number of inputs the model takes, this has to be MODEL_INPUTS
+    pub inputs: u32,
+    /// The label of each output of the model, in order
+    pub labels: LabelSet,
+    /// The feature pipeline the model was trained with, see devil_dsp::PIPELINE_ID
+    pub pipeline: u32,
+    /// How the inputs were normalized during training, the same has to be done before inference
+    pub normalization: Normalization,
+    /// A hash of the data the model was trained on, see hash_data. This is only used to tell models apart.
+    pub data_hash: u64,
+}
+
+/// Why a model couldn't be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    /// The bytes don't start with MAGIC, so it isn't a model file (or is one from before the header was added)
+    BadMagic,
+    /// The model file is of a different FORMAT_VERSION
+    UnsupportedVersion(u16),
+    /// The file ends before the header does
+    Truncated,
+    /// The header couldn't be decoded
+    BadHeader(postcard::Error),
+    /// The model takes a different number of inputs than this build gives it
+    InputMismatch { expected: usize, found: usize },
+    /// The labels don't start with the rejection class, see labels.rs
+    BadLabels,
+    /// The model was trained with a different feature pipeline
+    PipelineMismatch { expected: u32, found: u32 },
+    /// The burn record couldn't be decoded
+    BadRecord(RecorderError),
+}
+
+impl ModelHeader {
+    /// The header of a model trained with this build
+    pub fn new(labels: LabelSet, normalization: Normalization, data_hash: u64) -> Self {
+        Self {
+            inputs: MODEL_INPUTS as u32,
+            labels,
+            pipeline: devil_dsp::PIPELINE_ID,
+            normalization,
+            data_hash,
+        }
+    }
+
+    /// Wrap the burn record in a container with this header
+    pub fn encode(&self, record: &[u8]) -> Vec<u8> {
+        let header = postcard::to_allocvec(self).expect("The header should always encode");
+
+        let mut bytes = Vec::with_capacity(PREFIX_LEN + header.len() + record.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(record);
+        bytes
+    }
+
+    /// Split a container into its header and the burn record, without checking the header against this build
+    pub fn decode(bytes: &[u8]) -> Result<(Self, &[u8]), LoadError> {
+        if bytes.len() < PREFIX_LEN {
+            return Err(LoadError::Truncated);
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+
+        let len = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]) as usize;
+        let rest = &bytes[PREFIX_LEN..];
+        if rest.len() < len {
+            return Err(LoadError::Truncated);
+        }
+
+        let (header, record) = rest.split_at(len);
+        let header = postcard::from_bytes(header).map_err(LoadError::BadHeader)?;
+        Ok((header, record))
+    }
+
+    /// Check that a model with this header can be used with this build
+    pub fn validate(&self) -> Result<(), LoadError> {
+        if self.inputs as usize != MODEL_INPUTS {
+            return Err(LoadError::InputMismatch {
+                expected: MODEL_INPUTS,
+                found: self.inputs as usize,
+            });
+        }
+        if !self.labels.is_valid() {
+            return Err(LoadError::BadLabels);
+        }
+        if self.pipeline != devil_dsp::PIPELINE_ID {
+            return Err(LoadError::PipelineMismatch {
+                expected: devil_dsp::PIPELINE_ID,
+                found: self.pipeline,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// FNV-1a hash of the training data, stored in the header
+pub fn hash_data(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> ModelHeader {
+        ModelHeader::new(
+            LabelSet::from_labels(["fist", "flex"]),
+            Normalization::PerChannelMinMax,
+            hash_data(b"training data"),
+        )
+    }
+
+    #[test]
+    fn round_trips_the_header_and_record() {
+        let header = header();
+        let bytes = header.encode(&[1, 2, 3]);
+
+        let (decoded, record) = ModelHeader::decode(&bytes).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(record, &[1, 2, 3]);
+        assert!(decoded.validate().is_ok());
+    }
+
+    #[test]
+    fn refuses_bytes_without_the_magic() {
+        let mut bytes = header().encode(&[]);
+        bytes[0] = b'X';
+        assert!(matches!(
+            ModelHeader::decode(&bytes),
+            Err(LoadError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn refuses_another_format_version() {
+        let mut bytes = header().encode(&[]);
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            ModelHeader::decode(&bytes),
+            Err(LoadError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn refuses_a_truncated_header() {
+        let bytes = header().encode(&[]);
+        // Cut off in the prefix, and then in the header itself
+        assert!(matches!(
+            ModelHeader::decode(&bytes[..PREFIX_LEN - 1]),
+            Err(LoadError::Truncated)
+        ));
+        assert!(matches!(
+            ModelHeader::decode(&bytes[..bytes.len() - 1]),
+            Err(LoadError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn refuses_a_model_with_other_inputs() {
+        let header = ModelHeader {
+            inputs: MODEL_INPUTS as u32 + 1,
+            ..header()
+        };
+        assert!(matches!(
+            header.validate(),
+            Err(LoadError::InputMismatch { expected, found }) if expected == MODEL_INPUTS && found == MODEL_INPUTS + 1
+        ));
+    }
+
+    #[test]
+    fn refuses_a_model_from_another_pipeline() {
+        let header = ModelHeader {
+            pipeline: devil_dsp::PIPELINE_ID ^ 1,
+            ..header()
+        };
+        assert!(matches!(
+            header.validate(),
+            Err(LoadError::PipelineMismatch { found, .. }) if found == devil_dsp::PIPELINE_ID ^ 1
+        ));
+    }
+
+    #[test]
+    fn refuses_labels_without_the_rejection_class() {
+        let bytes = header().encode(&[]);
+        let (mut header, _) = ModelHeader::decode(&bytes).unwrap();
+        // A LabelSet is only ever built with the rejection class first, so this one has to come from the wire
+        header.labels =
+            postcard::from_bytes(&postcard::to_allocvec(&alloc::vec!["fist"]).unwrap()).unwrap();
+        assert!(matches!(header.validate(), Err(LoadError::BadLabels)));
+    }
+}
@@ -0,0 +1,122 @@
+// The gestures a model can tell apart. These aren't hard coded, training learns whatever labels appear in the
+// data, and the label set is stored in the model header so that devil-embedded knows what each output means.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+/// The rejection class, this is always the first output of the model. It is predicted for anything that isn't
+/// one of the other gestures, and data labeled with it is trained like any other label.
+pub const UNKNOWN: &str = "unknown";
+
+/// The label of each output of the model, in order. The first label is always UNKNOWN.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LabelSet {
+    labels: Vec<String>,
+}
+
+impl Default for LabelSet {
+    // Only the rejection class
+    fn default() -> Self {
+        Self {
+            labels: alloc::vec![String::from(UNKNOWN)],
+        }
+    }
+}
+
+impl LabelSet {
+    /// Build the label set of some data, each distinct label is added in the order it first appears
+    pub fn from_labels<S: AsRef<str>>(labels: impl IntoIterator<Item = S>) -> Self {
+        let mut set = Self::default();
+        for label in labels {
+            let label = label.as_ref().trim();
+            if set.index(label).is_none() {
+                set.labels.push(String::from(label));
+            }
+        }
+        set
+    }
+
+    /// The number of outputs the model has, one for each label
+    pub fn outputs(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Whether the set starts with the rejection class and has no label twice, a set read from a model header
+    /// might not
+    pub fn is_valid(&self) -> bool {
+        let unique = self
+            .labels
+            .iter()
+            .enumerate()
+            .all(|(index, label)| !self.labels[..index].contains(label));
+        self.labels.first().map(String::as_str) == Some(UNKNOWN) && unique
+    }
+
+    /// The output which corresponds to label, if the model knows it
+    pub fn index(&self, label: &str) -> Option<usize> {
+        self.labels.iter().position(|known| known == label)
+    }
+
+    /// The output a label is trained and scored as, labels the model doesn't know are the rejection class
+    pub fn output(&self, label: &str) -> usize {
+        self.index(label).unwrap_or(0)
+    }
+
+    /// The label of an output, anything out of range is treated as UNKNOWN
+    pub fn label(&self, index: usize) -> &str {
+        self.labels.get(index).map_or(UNKNOWN, String::as_str)
+    }
+
+    /// Every label, in the order of the outputs
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.labels.iter().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_is_always_the_first_output() {
+        let labels = LabelSet::from_labels(["fist", "flex", "unknown", " fist "]);
+        assert_eq!(
+            labels.iter().collect::<Vec<_>>(),
+            ["unknown", "fist", "flex"]
+        );
+        assert_eq!(labels.outputs(), 3);
+        assert!(labels.is_valid());
+    }
+
+    #[test]
+    fn unseen_labels_are_the_rejection_class() {
+        let labels = LabelSet::from_labels(["fist", "flex"]);
+        assert_eq!(labels.output("flex"), 2);
+        assert_eq!(labels.output("wave"), 0);
+        assert_eq!(labels.index("wave"), None);
+        assert_eq!(labels.label(7), UNKNOWN);
+    }
+
+    #[test]
+    fn duplicates_are_not_valid() {
+        let labels = LabelSet {
+            labels: alloc::vec![
+                String::from(UNKNOWN),
+                String::from("fist"),
+                String::from("fist")
+            ],
+        };
+        assert!(!labels.is_valid());
+    }
+
+    #[test]
+    fn a_set_without_the_rejection_class_first_is_not_valid() {
+        let labels = LabelSet {
+            labels: alloc::vec![String::from("fist"), String::from(UNKNOWN)],
+        };
+        assert!(!labels.is_valid());
+    }
+}
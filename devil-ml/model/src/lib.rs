@@ -3,6 +3,8 @@
 
 // The purpose of this file is to be shared between inference and training.
 
+extern crate alloc;
+
 use burn::record::BinBytesRecorder;
 use burn::record::HalfPrecisionSettings;
 use burn::record::Recorder;
@@ -11,55 +13,28 @@ use burn::{
     prelude::*,
 };
 
-use num_enum::{IntoPrimitive, TryFromPrimitive};
+pub mod header;
+pub mod labels;
+pub mod normalize;
+pub mod smoothing;
+pub use devil_dsp::{CHANNELS, FEATURES_PER_CHANNEL};
+pub use header::{LoadError, ModelHeader};
+pub use labels::{LabelSet, UNKNOWN};
+pub use normalize::{ChannelStats, Normalization};
+pub use smoothing::{Smoother, SmoothingConfig};
 
 // Basic model structure at the time of writing is like this
 // Inputs:Outputs
-// MODEL_INPUTS:10 -> 10:10 -> 10:Output::COUNT
+// MODEL_INPUTS:10 -> 10:10 -> 10:outputs
+// where outputs is the number of labels the model is trained on, see labels.rs
 // All are linear transformations
 
-// The number of inputs the model intakes
-pub const MODEL_INPUTS: usize = 192;
+// The number of inputs the model intakes, this is the size of a frame from devil-dsp
+pub const MODEL_INPUTS: usize = devil_dsp::FRAME_LEN;
 
 // The level of precision that the model should be stored at. Should be half on embedded device
 pub type PrecisionSetting = HalfPrecisionSettings;
 
-/// The possible outputs of the model. Update this if more is wanted.
-#[derive(Clone, Debug, PartialEq, TryFromPrimitive, IntoPrimitive)] // This creates helpers to convert the enum to and from numbers
-#[repr(usize)] // That conversion will be to and from usize
-pub enum Output {
-    Flex,
-    Fist,
-    PinkyToThumb,
-
-    Unknown, // Added for logging abilities in devil-embedded, don't add to count
-}
-
-// Always update this if changing the number of Output items
-impl Output {
-    pub const COUNT: usize = 3;
-
-    pub fn from_str(string: &str) -> Option<Self> {
-        match string {
-            "flex" => Some(Self::Flex),
-            "fist" => Some(Self::Fist),
-            "pinky-to-thumb" => Some(Self::PinkyToThumb),
-
-            _ => None,
-        }
-    }
-
-    pub fn to_str(&self) -> &str {
-        match self {
-            Self::Flex => "flex",
-            Self::Fist => "fist",
-            Self::PinkyToThumb => "pinky-to-thumb",
-
-            Self::Unknown => "unknown",
-        }
-    }
-}
-
 // Defines all of the different layers and fields being used, along with the device.
 // Read more about this all in burn.dev
 #[derive(Module, Debug)]
@@ -73,27 +48,48 @@ pub struct Model<B: Backend> {
 }
 
 impl<B: Backend> Model<B> {
-    // Create a model from embedded states, or a .bin file, as seen in the inference/lib.rs
-    pub fn from_embedded(device: &B::Device, embedded_states: &[u8]) -> Self {
-        // This reads the file and treats the model as a full precision one.
+    // Create a model from embedded states, or a .bin file, as seen in the inference/lib.rs. The header of the
+    // file is checked against this build first, see header.rs. Returns the model along with its header.
+    pub fn from_embedded(
+        device: &B::Device,
+        embedded_states: &[u8],
+    ) -> Result<(Self, ModelHeader), LoadError> {
+        let (header, states) = ModelHeader::decode(embedded_states)?;
+        header.validate()?;
+
+        // This reads the record with the precision it was saved at.
         // It then loads the stats into a vector on the heap, and initializes the model.
         let record = BinBytesRecorder::<PrecisionSetting>::default()
-            .load(embedded_states.to_vec(), device)
-            .expect("Should decode state successfully");
+            .load(states.to_vec(), device)
+            .map_err(LoadError::BadRecord)?;
+
+        // Returns a new device with the record, sized for the labels in the header
+        Ok((
+            Self::new(device, header.labels.outputs()).load_record(record),
+            header,
+        ))
+    }
 
-        // Returns a new device with the record
-        Self::new(device).load_record(record)
+    // Wrap the trained model in a model file with the given header, this is what from_embedded reads
+    pub fn to_bytes(
+        self,
+        header: &ModelHeader,
+    ) -> Result<alloc::vec::Vec<u8>, burn::record::RecorderError> {
+        let record =
+            BinBytesRecorder::<PrecisionSetting>::default().record(self.into_record(), ())?;
+        Ok(header.encode(&record))
     }
 
     // Creates a new Model with no states, initialized to whatever device backend is provided.
     // This is where the model really is defined, and as such, most optomizations to the model
     // should be placed here.
-    pub fn new(device: &B::Device) -> Self {
+    // outputs is the number of labels, see LabelSet::outputs.
+    pub fn new(device: &B::Device, outputs: usize) -> Self {
         Self {
             linear1: LinearConfig::new(MODEL_INPUTS, 10).init(device),
             linear2: LinearConfig::new(10, 10).init(device),
             linear3: LinearConfig::new(10, 10).init(device),
-            linear4: LinearConfig::new(10, Output::COUNT).init(device),
+            linear4: LinearConfig::new(10, outputs).init(device),
             activation: Relu::new(),
             dropout: DropoutConfig::new(0.2).init(),
         }
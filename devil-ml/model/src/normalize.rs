@@ -0,0 +1,197 @@
+// Normalization of the feature frames before they are handed to the model. This lives in the model
+// crate so that devil-embedded and training both scale the inputs with the exact same code.
+
+use serde::{Deserialize, Serialize};
+
+use devil_dsp::{CHANNELS, FEATURES_PER_CHANNEL};
+
+use crate::MODEL_INPUTS;
+
+// Anything with a spread smaller than this is treated as a silent channel, to avoid dividing by zero
+const EPSILON: f32 = 1.0e-6;
+
+/// Per channel statistics recorded during calibration, used by the z-score normalization.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelStats {
+    pub mean: [f32; CHANNELS],
+    pub std_dev: [f32; CHANNELS],
+}
+
+impl ChannelStats {
+    /// Statistics which leave the inputs untouched, used until a calibration has been done.
+    pub const fn identity() -> Self {
+        Self {
+            mean: [0.0; CHANNELS],
+            std_dev: [1.0; CHANNELS],
+        }
+    }
+}
+
+impl Default for ChannelStats {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+/// The different ways a frame of inputs can be normalized. Each channel is always scaled on its own,
+/// so that one loud electrode does not flatten the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Scale every channel between 0 and 1 using its own min and max
+    #[default]
+    PerChannelMinMax,
+    /// Subtract the calibrated mean and divide by the calibrated standard deviation of each channel
+    ZScore(ChannelStats),
+    /// Compress the range of the inputs with ln(1 + |x|)
+    LogMagnitude,
+}
+
+impl Normalization {
+    /// Normalize a frame in place. The frame must contain CHANNELS * FEATURES_PER_CHANNEL inputs.
+    /// A silent channel is set to all zeros instead of producing NaNs.
+    pub fn normalize(&self, frame: &mut [f32]) {
+        assert_eq!(
+            frame.len(),
+            MODEL_INPUTS,
+            "frame has the wrong number of inputs"
+        );
+
+        for (channel, inputs) in frame.chunks_exact_mut(FEATURES_PER_CHANNEL).enumerate() {
+            match self {
+                Self::PerChannelMinMax => {
+                    let min = inputs.iter().copied().fold(f32::INFINITY, f32::min);
+                    let max = inputs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                    let range = max - min;
+
+                    if range < EPSILON {
+                        inputs.fill(0.0);
+                    } else {
+                        inputs.iter_mut().for_each(|x| *x = (*x - min) / range);
+                    }
+                }
+                Self::ZScore(stats) => {
+                    let mean = stats.mean[channel];
+                    let std_dev = stats.std_dev[channel];
+
+                    if std_dev < EPSILON {
+                        inputs.fill(0.0);
+                    } else {
+                        inputs.iter_mut().for_each(|x| *x = (*x - mean) / std_dev);
+                    }
+                }
+                Self::LogMagnitude => {
+                    inputs
+                        .iter_mut()
+                        .for_each(|x| *x = libm::log1pf(libm::fabsf(*x)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every channel counts up from channel * 10 in steps of channel + 1, so each has a different range
+    fn frame() -> [f32; MODEL_INPUTS] {
+        let mut frame = [0.0; MODEL_INPUTS];
+        for (channel, inputs) in frame.chunks_exact_mut(FEATURES_PER_CHANNEL).enumerate() {
+            for (index, input) in inputs.iter_mut().enumerate() {
+                *input = (channel * 10 + index * (channel + 1)) as f32;
+            }
+        }
+        frame
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1.0e-5,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn min_max_scales_each_channel_on_its_own() {
+        let mut frame = frame();
+        Normalization::PerChannelMinMax.normalize(&mut frame);
+
+        let last = (FEATURES_PER_CHANNEL - 1) as f32;
+        for inputs in frame.chunks_exact(FEATURES_PER_CHANNEL) {
+            for (index, input) in inputs.iter().enumerate() {
+                assert_close(*input, index as f32 / last);
+            }
+        }
+    }
+
+    #[test]
+    fn min_max_zeroes_a_constant_channel() {
+        let mut frame = frame();
+        frame[..FEATURES_PER_CHANNEL].fill(42.0);
+        Normalization::PerChannelMinMax.normalize(&mut frame);
+
+        assert!(frame[..FEATURES_PER_CHANNEL]
+            .iter()
+            .all(|input| *input == 0.0));
+        // The other channels are scaled as usual
+        assert_close(frame[2 * FEATURES_PER_CHANNEL - 1], 1.0);
+    }
+
+    #[test]
+    fn z_score_uses_the_stats_of_each_channel() {
+        let mut stats = ChannelStats::identity();
+        for channel in 0..CHANNELS {
+            stats.mean[channel] = channel as f32 * 10.0;
+            stats.std_dev[channel] = channel as f32 + 1.0;
+        }
+        let mut frame = frame();
+        Normalization::ZScore(stats).normalize(&mut frame);
+
+        // Taking off the start of each channel and dividing by its step leaves the index
+        for inputs in frame.chunks_exact(FEATURES_PER_CHANNEL) {
+            for (index, input) in inputs.iter().enumerate() {
+                assert_close(*input, index as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn z_score_zeroes_a_channel_without_spread() {
+        let mut stats = ChannelStats::identity();
+        stats.std_dev[1] = 0.0;
+        let mut frame = frame();
+        Normalization::ZScore(stats).normalize(&mut frame);
+
+        let second = &frame[FEATURES_PER_CHANNEL..2 * FEATURES_PER_CHANNEL];
+        assert!(second.iter().all(|input| *input == 0.0));
+        assert!(frame.iter().all(|input| input.is_finite()));
+    }
+
+    #[test]
+    fn identity_stats_leave_the_frame_untouched() {
+        let mut normalized = frame();
+        Normalization::ZScore(ChannelStats::default()).normalize(&mut normalized);
+        assert_eq!(normalized, frame());
+    }
+
+    #[test]
+    fn log_magnitude_compresses_both_signs() {
+        let mut frame = [0.0; MODEL_INPUTS];
+        frame[0] = core::f32::consts::E - 1.0;
+        frame[1] = 1.0 - core::f32::consts::E;
+        Normalization::LogMagnitude.normalize(&mut frame);
+
+        assert_close(frame[0], 1.0);
+        assert_close(frame[1], 1.0);
+        assert_eq!(frame[2], 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong number of inputs")]
+    fn refuses_a_frame_of_the_wrong_length() {
+        let mut frame = [0.0; MODEL_INPUTS + 1];
+        Normalization::PerChannelMinMax.normalize(&mut frame);
+    }
+}
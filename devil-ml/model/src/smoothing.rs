@@ -0,0 +1,209 @@
+// Turns the prediction of every single frame into a steady decision, so the hand doesn't chatter between poses.
+// A prediction has to be confident enough to count, then has to win a vote over the last few frames, and then has
+// to keep winning for a dwell time before it replaces the current decision.
+//
+// This lives in the model crate so that devil-embedded and the offline evaluator in training run the exact same
+// code, and its effect can be measured on recorded data.
+
+use alloc::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+/// The settings of the Smoother
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SmoothingConfig {
+    /// Predictions with a lower probability than this are ignored, and leave the decision as it is
+    pub min_confidence: f32,
+    /// The number of recent frames which vote, the M of N-of-M
+    pub window: u8,
+    /// How many of those frames have to agree on an output, the N of N-of-M
+    pub votes: u8,
+    /// How long an output has to keep winning the vote before it becomes the decision
+    pub dwell_ms: u32,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            min_confidence: 0.6,
+            window: 5,
+            votes: 3,
+            dwell_ms: 100,
+        }
+    }
+}
+
+impl SmoothingConfig {
+    /// Whether the vote can only ever have a single winner. votes has to be more than half of the window, or two
+    /// outputs could both win at once, and no more than the window, or nothing could ever win.
+    pub fn is_valid(&self) -> bool {
+        self.votes >= 1
+            && self.votes <= self.window
+            && self.votes as usize * 2 > self.window as usize
+            && (0.0..=1.0).contains(&self.min_confidence)
+    }
+}
+
+/// Keeps the recent predictions, and decides which output is being made
+pub struct Smoother {
+    config: SmoothingConfig,
+    // The output of each recent frame, None if it wasn't confident enough
+    history: VecDeque<Option<usize>>,
+    // The output that won the vote, and when it started winning
+    pending: Option<(usize, u64)>,
+    decision: Option<usize>,
+}
+
+impl Smoother {
+    pub fn new(config: SmoothingConfig) -> Self {
+        Self {
+            config,
+            history: VecDeque::with_capacity(config.window as usize),
+            pending: None,
+            decision: None,
+        }
+    }
+
+    /// Forget everything, there is no decision afterwards
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.pending = None;
+        self.decision = None;
+    }
+
+    /// Add the prediction of a frame, output is the most likely output and confidence its probability. now_ms
+    /// is the time of the frame in milliseconds, from any starting point. Returns the current decision, which is
+    /// None until an output has been accepted.
+    pub fn update(&mut self, output: usize, confidence: f32, now_ms: u64) -> Option<usize> {
+        if self.history.len() >= self.config.window.max(1) as usize {
+            self.history.pop_front();
+        }
+        self.history
+            .push_back((confidence >= self.config.min_confidence).then_some(output));
+
+        match self.winner() {
+            // Nothing has enough votes, so keep the decision as it is
+            None => self.pending = None,
+            Some(winner) if Some(winner) == self.decision => self.pending = None,
+            Some(winner) => {
+                let since = match self.pending {
+                    Some((pending, since)) if pending == winner => since,
+                    _ => now_ms,
+                };
+                if now_ms.saturating_sub(since) >= self.config.dwell_ms as u64 {
+                    self.decision = Some(winner);
+                    self.pending = None;
+                } else {
+                    self.pending = Some((winner, since));
+                }
+            }
+        }
+
+        self.decision
+    }
+
+    /// The current decision
+    pub fn decision(&self) -> Option<usize> {
+        self.decision
+    }
+
+    // The output with at least `votes` votes in the history, if there is one. Only a single output can have
+    // that many while the config is valid.
+    fn winner(&self) -> Option<usize> {
+        self.history.iter().flatten().copied().find(|candidate| {
+            let votes = self
+                .history
+                .iter()
+                .filter(|output| **output == Some(*candidate))
+                .count();
+            votes >= self.config.votes as usize
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smoother(window: u8, votes: u8, dwell_ms: u32) -> Smoother {
+        Smoother::new(SmoothingConfig {
+            min_confidence: 0.6,
+            window,
+            votes,
+            dwell_ms,
+        })
+    }
+
+    #[test]
+    fn unconfident_predictions_leave_the_decision() {
+        let mut smoother = smoother(3, 2, 0);
+        smoother.update(1, 0.9, 0);
+        assert_eq!(smoother.update(1, 0.9, 10), Some(1));
+
+        // However many there are, they don't count towards anything
+        for frame in 2..10 {
+            assert_eq!(smoother.update(2, 0.5, frame * 10), Some(1));
+        }
+    }
+
+    #[test]
+    fn an_output_needs_votes_of_the_window() {
+        let mut smoother = smoother(5, 3, 0);
+        assert_eq!(smoother.update(1, 0.9, 0), None);
+        assert_eq!(smoother.update(2, 0.9, 10), None);
+        assert_eq!(smoother.update(1, 0.9, 20), None);
+        assert_eq!(smoother.update(2, 0.9, 30), None);
+        assert_eq!(smoother.update(1, 0.9, 40), Some(1));
+
+        // The first vote for 1 drops out of the window, which gives 2 three of the five
+        assert_eq!(smoother.update(2, 0.9, 50), Some(2));
+    }
+
+    #[test]
+    fn an_output_has_to_keep_winning_for_the_dwell_time() {
+        let mut smoother = smoother(1, 1, 100);
+        assert_eq!(smoother.update(1, 0.9, 0), None);
+        assert_eq!(smoother.update(1, 0.9, 50), None);
+        assert_eq!(smoother.update(1, 0.9, 100), Some(1));
+
+        // Losing the vote part way through starts the dwell again
+        assert_eq!(smoother.update(2, 0.9, 110), Some(1));
+        assert_eq!(smoother.update(1, 0.9, 120), Some(1));
+        assert_eq!(smoother.update(2, 0.9, 130), Some(1));
+        assert_eq!(smoother.update(2, 0.9, 200), Some(1));
+        assert_eq!(smoother.update(2, 0.9, 230), Some(2));
+    }
+
+    #[test]
+    fn reset_forgets_the_decision_and_the_votes() {
+        let mut smoother = smoother(3, 2, 0);
+        smoother.update(1, 0.9, 0);
+        smoother.update(1, 0.9, 10);
+        smoother.reset();
+        assert_eq!(smoother.decision(), None);
+
+        // The old votes are gone, so a single new one isn't enough
+        assert_eq!(smoother.update(1, 0.9, 20), None);
+    }
+
+    #[test]
+    fn only_a_majority_vote_is_valid() {
+        let config = |window, votes| SmoothingConfig {
+            window,
+            votes,
+            ..SmoothingConfig::default()
+        };
+        assert!(SmoothingConfig::default().is_valid());
+        assert!(config(5, 3).is_valid());
+        assert!(config(1, 1).is_valid());
+        assert!(!config(5, 2).is_valid());
+        assert!(!config(4, 2).is_valid());
+        assert!(!config(5, 6).is_valid());
+        assert!(!config(0, 0).is_valid());
+        assert!(!SmoothingConfig {
+            min_confidence: f32::NAN,
+            ..SmoothingConfig::default()
+        }
+        .is_valid());
+    }
+}
@@ -1,10 +1,12 @@
 use csv::Writer;
-use devil_ml_model::Output;
+use devil_dsp::window::WINDOW_TAG;
+use devil_dsp::{WindowFunction, WINDOW_FUNCTION};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use std::env;
 use std::error::Error;
-use std::fs::create_dir_all;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
 use std::path::PathBuf;
 
 // Macro which allows the user to print out to console despite being in a build.rs file
@@ -17,6 +19,20 @@ macro_rules! p {
 
 fn main() -> Result<(), Box<dyn Error>> {
     let arm_data = include_str!("./data/savannah's arm v4.csv");
+
+    // Check that the data was recorded with the same window function that devil-dsp is using now, a model
+    // trained on other features would get inputs on the arm it has never seen
+    let window =
+        WindowFunction::from_tag(arm_data).expect("Data is tagged with an unknown window function");
+    if window != WINDOW_FUNCTION {
+        return Err(format!(
+            "data was recorded with the {} window, but devil-dsp uses {}, record new data",
+            window.name(),
+            WINDOW_FUNCTION.name()
+        )
+        .into());
+    }
+
     let mut parse_arm_data = parse_csv(arm_data);
 
     parse_arm_data.iter().take(10).for_each(|item| {
@@ -54,9 +70,9 @@ fn main() -> Result<(), Box<dyn Error>> {
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     let data_folder = out_path.join("data");
     create_dir_all(&data_folder)?;
-    write_csv(train_data, data_folder.join("train.csv"))?;
-    write_csv(validation_data, data_folder.join("validation.csv"))?;
-    write_csv(testing_data, data_folder.join("testing.csv"))?;
+    write_csv(train_data, window, data_folder.join("train.csv"))?;
+    write_csv(validation_data, window, data_folder.join("validation.csv"))?;
+    write_csv(testing_data, window, data_folder.join("testing.csv"))?;
 
     println!(
         "out_dir: {:?}",
@@ -68,8 +84,16 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn write_csv(data: Vec<DevilItem>, path: PathBuf) -> Result<(), Box<dyn Error>> {
-    let mut wtr = Writer::from_path(path)?;
+fn write_csv(
+    data: Vec<DevilItem>,
+    window: WindowFunction,
+    path: PathBuf,
+) -> Result<(), Box<dyn Error>> {
+    // Keep the window tag, so that training knows which window the data was recorded with
+    let mut file = File::create(path)?;
+    writeln!(file, "{}{}", WINDOW_TAG, window.name())?;
+
+    let mut wtr = Writer::from_writer(file);
 
     for item in data {
         let inputs = item
@@ -77,7 +101,7 @@ fn write_csv(data: Vec<DevilItem>, path: PathBuf) -> Result<(), Box<dyn Error>>
             .iter()
             .map(|i| i.to_string())
             .collect::<Vec<_>>();
-        let label = item.label.to_str().to_string();
+        let label = item.label;
 
         wtr.write_record(inputs.iter().chain(std::iter::once(&label)))?;
     }
@@ -90,13 +114,14 @@ fn write_csv(data: Vec<DevilItem>, path: PathBuf) -> Result<(), Box<dyn Error>>
 #[derive(Clone, Debug)]
 pub struct DevilItem {
     pub inputs: Vec<f32>,
-    pub label: Output,
+    pub label: String,
 }
 
 pub fn parse_csv(input: &str) -> Vec<DevilItem> {
     // Initialize csv reader
     let mut rdr = csv::ReaderBuilder::new()
         .has_headers(false) // Our files have no headers
+        .comment(Some(b'#')) // Skip the window tag
         .from_reader(input.as_bytes()); // the string as bytes is the input
 
     // The vector which will store all the DevilItems
@@ -123,20 +148,10 @@ pub fn parse_csv(input: &str) -> Vec<DevilItem> {
                     .map(|s| s.parse::<f32>().expect("Found non number in csv")) // Filter out invalid f32
                     .collect();
 
-                // parsing label to Output
-                let label = label.to_string();
-                let label = match Output::from_str(&label) {
-                    Some(val) => val,
-                    None => {
-                        println!("Failed to parse label '{}'", label);
-                        continue;
-                    },
-                };
-
                 // Push the item to the vector
                 output.push(DevilItem {
-                    inputs, // First 196 items as f32
-                    label,  // Output
+                    inputs,                          // First 196 items as f32
+                    label: label.trim().to_string(), // The label, whatever it is
                 });
             }
             Err(_) => continue,
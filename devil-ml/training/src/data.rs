@@ -7,22 +7,24 @@ use burn::{
 };
 
 use csv;
-use devil_ml_model::Output;
+use devil_dsp::{FeatureExtractor, Sample, WindowFunction, CHANNELS, WINDOW_FUNCTION};
+use devil_ml_model::{LabelSet, Normalization, MODEL_INPUTS};
 
 // This file just creates the batching logic, just a bunch of boiler plate, based upon
 // https://burn.dev/burn-book/basic-workflow/data.html
 // and https://burn.dev/burn-book/building-blocks/dataset.html
 
-//  Batcher's structure, just includes the device.
+//  Batcher's structure, includes the device, and the labels used to turn each label into the index of an output.
 #[derive(Clone)]
 pub struct DevilBatcher<B: Backend> {
     device: B::Device,
+    labels: LabelSet,
 }
 
 impl<B: Backend> DevilBatcher<B> {
-    // Creates new batcher with device
-    pub fn new(device: B::Device) -> Self {
-        Self { device }
+    // Creates new batcher with device and the labels the model is trained on
+    pub fn new(device: B::Device, labels: LabelSet) -> Self {
+        Self { device, labels }
     }
 }
 
@@ -41,12 +43,12 @@ pub struct DevilDataset {
     dataset: InMemDataset<DevilItem>,
 }
 
-// Just stores a [f32; MODEL_INPUTS] vector of sensor inputs, and a label as a number, will change to
-// an enum in the future
+// Just stores a [f32; MODEL_INPUTS] vector of sensor inputs, and the label it was recorded with. The labels
+// aren't fixed, the model is trained on whichever labels appear in the data, see devil_ml_model::LabelSet.
 #[derive(Clone, Debug)]
 pub struct DevilItem {
     pub inputs: Vec<f32>,
-    pub label: Output,
+    pub label: String,
 }
 
 // Makes DevilDataset implement the Dataset trait, allows it to be used as a dataset
@@ -61,9 +63,33 @@ impl Dataset<DevilItem> for DevilDataset {
 }
 
 impl DevilDataset {
-    /// Converts the csv as a string into a DevilDataset
-    pub fn new(csv: &str) -> Self {
-        let items = Self::from_csv(csv);
+    /// Converts the csv as a string into a DevilDataset, normalizing every item with the given strategy.
+    /// This must be the same strategy that devil-embedded uses, otherwise the model sees different inputs.
+    pub fn new(csv: &str, normalization: &Normalization) -> Self {
+        // Features computed with a different window function than devil-dsp uses won't match the chip
+        match WindowFunction::from_tag(csv) {
+            Some(window) if window == WINDOW_FUNCTION => (),
+            Some(window) => eprintln!(
+                "data was recorded with the {} window, but devil-dsp uses {}",
+                window.name(),
+                WINDOW_FUNCTION.name()
+            ),
+            None => eprintln!("data is tagged with an unknown window function"),
+        }
+
+        Self::from_items(Self::from_csv(csv), normalization)
+    }
+
+    /// Same as new, but for a csv of raw samples, see from_raw_csv
+    pub fn from_raw(csv: &str, normalization: &Normalization) -> Self {
+        Self::from_items(Self::from_raw_csv(csv), normalization)
+    }
+
+    // Normalizes the items, then stores them in an InMemDataset
+    fn from_items(mut items: Vec<DevilItem>, normalization: &Normalization) -> Self {
+        items
+            .iter_mut()
+            .for_each(|item| normalization.normalize(&mut item.inputs));
 
         // Create an InMemDataset of DevilItems
         let dataset = InMemDataset::new(items);
@@ -76,6 +102,7 @@ impl DevilDataset {
         // Initialize csv reader
         let mut rdr = csv::ReaderBuilder::new()
             .has_headers(false) // Our files have no headers
+            .comment(Some(b'#')) // Skip the window tag
             .from_reader(input.as_bytes()); // the string as bytes is the input
 
         // The vector which will store all the DevilItems
@@ -96,26 +123,25 @@ impl DevilDataset {
                         continue;
                     };
 
-                    // Parse all of the inputs into f32's
-                    let inputs: Vec<f32> = inputs
-                        .into_iter()
-                        .map(|s| s.parse::<f32>().expect("Found non number in csv")) // Filter out invalid f32
-                        .collect();
-
-                    // parsing label to Output
-                    let label = label.to_string();
-                    let label = match Output::from_str(&label) {
-                        Some(val) => val,
-                        None => {
-                            println!("Failed to parse label '{}'", label);
-                            continue;
-                        },
+                    // Parse all of the inputs into f32's, skip the row if one isn't a number or there are the wrong
+                    // number of them, it would only trip up the normalization later
+                    let Ok(inputs) = inputs
+                        .iter()
+                        .map(|s| s.trim().parse::<f32>())
+                        .collect::<Result<Vec<f32>, _>>()
+                    else {
+                        eprintln!("row has a value which isn't a number: {:?}", values);
+                        continue;
                     };
+                    if inputs.len() != MODEL_INPUTS {
+                        eprintln!("row has {} inputs, expected {}", inputs.len(), MODEL_INPUTS);
+                        continue;
+                    }
 
                     // Push the item to the vector
                     output.push(DevilItem {
-                        inputs, // First 196 items as f32
-                        label,  // Output
+                        inputs,                          // First 196 items as f32
+                        label: label.trim().to_string(), // The label, whatever it is
                     });
                 }
                 Err(_) => continue,
@@ -124,6 +150,65 @@ impl DevilDataset {
 
         return output;
     }
+
+    /// Return an vector of DevilItems from a csv of raw samples. Each row is a single sample from every
+    /// sensor followed by the label, like `2048,2051,2039,fist`. The samples are run through the same
+    /// FeatureExtractor as devil-embedded, with disjoint windows so that no sample ends up in two items.
+    pub fn from_raw_csv(input: &str) -> Vec<DevilItem> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .comment(Some(b'#'))
+            .from_reader(input.as_bytes());
+
+        let mut output: Vec<DevilItem> = Vec::new();
+
+        // The extractor is restarted whenever the label changes, so a window never mixes two gestures
+        let mut extractor = FeatureExtractor::default();
+        let mut current_label: Option<String> = None;
+
+        for row in rdr.records() {
+            let Ok(row) = row else {
+                continue;
+            };
+
+            let values: Vec<&str> = row.iter().collect();
+            let Some((label, samples)) = values.split_last() else {
+                eprintln!("row is improperly formatted");
+                continue;
+            };
+
+            let label = label.trim().to_string();
+
+            // Parse the samples into a single Sample, skip the row if a sample isn't a number or the number of
+            // sensors is wrong
+            let Ok(samples) = samples
+                .iter()
+                .map(|s| s.trim().parse::<u16>())
+                .collect::<Result<Vec<u16>, _>>()
+            else {
+                eprintln!("row has a value which isn't a sample: {:?}", values);
+                continue;
+            };
+            let Ok(sample) = Sample::try_from(samples.as_slice()) else {
+                eprintln!("row has {} samples, expected {}", samples.len(), CHANNELS);
+                continue;
+            };
+
+            if current_label.as_ref() != Some(&label) {
+                extractor = FeatureExtractor::default();
+                current_label = Some(label.clone());
+            }
+
+            if let Some(frame) = extractor.push(sample) {
+                output.push(DevilItem {
+                    inputs: frame.to_vec(),
+                    label,
+                });
+            }
+        }
+
+        output
+    }
 }
 
 // Batcher function, this gets called when burn wants to batch everything.
@@ -136,17 +221,15 @@ impl<B: Backend> Batcher<DevilItem, DevilBatch<B>> for DevilBatcher<B> {
             .map(|data| Tensor::<B, 1>::from_data(data, &self.device).unsqueeze())
             .collect();
 
-        // You typically do normalization in the function above, but with our setup, we expect things to be
-        // already normalized, b/c we do that on the chip.
+        // Normalization is already done when the DevilDataset is created, with the same strategy as the chip.
 
         // Return a vector of classification with a 1d int tensor.
         let targets = items
             .iter()
             .map(|item| {
-                Tensor::<B, 1, Int>::from_data(
-                    [(item.label.clone() as u8).elem::<B::IntElem>()],
-                    &self.device,
-                )
+                // Labels which aren't in the set are trained as the rejection class
+                let index = self.labels.output(&item.label);
+                Tensor::<B, 1, Int>::from_data([(index as u8).elem::<B::IntElem>()], &self.device)
             })
             .collect();
 
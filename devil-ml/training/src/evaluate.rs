@@ -0,0 +1,133 @@
+use burn::prelude::*;
+use burn::tensor::activation::softmax;
+use devil_ml_model::{LabelSet, Model, Smoother, SmoothingConfig};
+
+use crate::data::DevilItem;
+
+// The offline evaluator, which runs a model over a recording in the order it was recorded, and measures the
+// predictions of each frame against the decisions of the same Smoother devil-embedded uses.
+
+/// How well a model does on a recording, frame by frame and after smoothing
+#[derive(Clone, Debug, Default)]
+pub struct Evaluation {
+    pub frames: usize,
+    /// Frames where the most likely output was the label
+    pub raw_correct: usize,
+    /// Frames where the smoothed decision was the label
+    pub smoothed_correct: usize,
+    /// Frames before the Smoother made its first decision
+    pub undecided: usize,
+    /// How often the most likely output changed from one frame to the next
+    pub raw_changes: usize,
+    /// How often the smoothed decision changed, this is how often the arm would have moved
+    pub smoothed_changes: usize,
+}
+
+impl Evaluation {
+    pub fn raw_accuracy(&self) -> f32 {
+        self.raw_correct as f32 / self.frames.max(1) as f32
+    }
+
+    pub fn smoothed_accuracy(&self) -> f32 {
+        self.smoothed_correct as f32 / self.frames.max(1) as f32
+    }
+}
+
+/// Run the model over the items of a recording in order. The items have to be normalized already, and be
+/// frame_period_ms apart, which is what the dwell time of the Smoother is measured against.
+pub fn evaluate<B: Backend>(
+    model: &Model<B>,
+    labels: &LabelSet,
+    items: impl IntoIterator<Item = DevilItem>,
+    smoothing: SmoothingConfig,
+    frame_period_ms: u64,
+    device: &B::Device,
+) -> Evaluation {
+    let predictions = items.into_iter().map(|item| {
+        let input: Tensor<B, 1> = Tensor::from_data(item.inputs.as_slice(), device);
+        let inference = softmax(devil_ml_model::infer(input, model), 0);
+
+        // The most likely output and its probability
+        let (output, confidence) = inference
+            .into_data()
+            .as_slice::<f32>()
+            .unwrap()
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap())
+            .unwrap();
+
+        // Labels the model doesn't know are expected to be rejected, the same as in training
+        (output, confidence, labels.output(&item.label))
+    });
+
+    score(predictions, smoothing, frame_period_ms)
+}
+
+/// Score predictions made frame_period_ms apart, each the most likely output, its probability and the output
+/// that was expected
+pub fn score(
+    predictions: impl IntoIterator<Item = (usize, f32, usize)>,
+    smoothing: SmoothingConfig,
+    frame_period_ms: u64,
+) -> Evaluation {
+    let mut smoother = Smoother::new(smoothing);
+    let mut evaluation = Evaluation::default();
+    let mut previous_raw = None;
+    let mut previous_decision = None;
+
+    for (frame, (output, confidence, expected)) in predictions.into_iter().enumerate() {
+        let decision = smoother.update(output, confidence, frame as u64 * frame_period_ms);
+
+        evaluation.frames += 1;
+        evaluation.raw_correct += (output == expected) as usize;
+        evaluation.smoothed_correct += (decision == Some(expected)) as usize;
+        evaluation.undecided += decision.is_none() as usize;
+        evaluation.raw_changes += (previous_raw.is_some() && previous_raw != Some(output)) as usize;
+        evaluation.smoothed_changes +=
+            (previous_decision.is_some() && previous_decision != decision) as usize;
+
+        previous_raw = Some(output);
+        previous_decision = decision;
+    }
+
+    evaluation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Needs two of the last three frames, and no dwell
+    fn smoothing() -> SmoothingConfig {
+        SmoothingConfig {
+            min_confidence: 0.6,
+            window: 3,
+            votes: 2,
+            dwell_ms: 0,
+        }
+    }
+
+    #[test]
+    fn smoothing_hides_a_single_wrong_frame() {
+        let outputs = [1, 1, 1, 2, 1, 1];
+        let evaluation = score(outputs.map(|output| (output, 0.9, 1)), smoothing(), 10);
+
+        assert_eq!(evaluation.frames, 6);
+        assert_eq!(evaluation.raw_correct, 5);
+        assert_eq!(evaluation.raw_changes, 2);
+        // Undecided until the second frame, then 1 the whole way through
+        assert_eq!(evaluation.undecided, 1);
+        assert_eq!(evaluation.smoothed_correct, 5);
+        assert_eq!(evaluation.smoothed_changes, 0);
+    }
+
+    #[test]
+    fn unconfident_frames_are_never_decided() {
+        let evaluation = score([(1, 0.5, 1); 4], smoothing(), 10);
+        assert_eq!(evaluation.raw_accuracy(), 1.0);
+        assert_eq!(evaluation.undecided, 4);
+        assert_eq!(evaluation.smoothed_accuracy(), 0.0);
+    }
+}
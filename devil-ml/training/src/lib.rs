@@ -1,8 +1,8 @@
 use burn::data::dataloader::DataLoaderBuilder;
-use burn::module::Module;
+use burn::data::dataset::Dataset;
 use burn::optim::AdamConfig;
 use burn::prelude::Config;
-use burn::record::{BinFileRecorder, CompactRecorder};
+use burn::record::CompactRecorder;
 use burn::tensor::backend::AutodiffBackend;
 use burn::train::metric::AccuracyMetric;
 use burn::train::metric::LossMetric;
@@ -12,11 +12,13 @@ use burn::train::renderer::TrainingProgress;
 use burn::train::LearnerBuilder;
 use data::DevilBatcher;
 use data::DevilDataset;
+use devil_ml_model::header::hash_data;
 use devil_ml_model::Model;
-use devil_ml_model::Output;
-use devil_ml_model::PrecisionSetting;
+use devil_ml_model::Normalization;
+use devil_ml_model::{LabelSet, ModelHeader};
 
 pub mod data;
+pub mod evaluate;
 pub mod training;
 
 // Uses a macro to add lots of functionality to this config, as seen in
@@ -37,6 +39,9 @@ pub struct TrainingConfig {
     pub learning_rate: f64,
     #[config(default = true)]
     pub custom_renderer: bool,
+    // How the inputs are normalized, has to match what devil-embedded does on the chip
+    #[config(default = "Normalization::PerChannelMinMax")]
+    pub normalization: Normalization,
 }
 
 // Removes, then creates the directory for the output of the model
@@ -65,11 +70,7 @@ impl MetricsRenderer for CustomRenderer {
 }
 
 /// Trains the model and outputs all of the byproducts to artifact_dir, using the specified backend device.
-pub fn train<B: AutodiffBackend>(
-    artifact_dir: &str,
-    config: TrainingConfig,
-    device: B::Device,
-) {
+pub fn train<B: AutodiffBackend>(artifact_dir: &str, config: TrainingConfig, device: B::Device) {
     create_artifact_dir(artifact_dir);
     config
         .save(format!("{artifact_dir}/config.json"))
@@ -78,18 +79,27 @@ pub fn train<B: AutodiffBackend>(
     // Set the seed for the random number generator used by the backend.
     B::seed(config.seed);
 
-    // Create the batcher for the training data
-    let batcher_train = DevilBatcher::<B>::new(device.clone());
-    // Create the batcher for the validation data
-    let batcher_valid = DevilBatcher::<B::InnerBackend>::new(device.clone());
-
     // Get the train and test csv's as &str's
     let train_input = include_str!(concat!(env!("OUT_DIR"), "/data/train.csv"));
     let validation_input = include_str!(concat!(env!("OUT_DIR"), "/data/validation.csv"));
 
     // Instantiate the dataset from the text
-    let dataset_train = DevilDataset::new(train_input);
-    let dataset_validation = DevilDataset::new(validation_input);
+    let dataset_train = DevilDataset::new(train_input, &config.normalization);
+    let dataset_validation = DevilDataset::new(validation_input, &config.normalization);
+
+    // The model learns whichever labels appear in the data, along with the rejection class
+    let labels = LabelSet::from_labels(
+        dataset_train
+            .iter()
+            .chain(dataset_validation.iter())
+            .map(|item| item.label),
+    );
+    println!("Training on labels: {:?}", labels);
+
+    // Create the batcher for the training data
+    let batcher_train = DevilBatcher::<B>::new(device.clone(), labels.clone());
+    // Create the batcher for the validation data
+    let batcher_valid = DevilBatcher::<B::InnerBackend>::new(device.clone(), labels.clone());
 
     // Creates a DataLoader for the train dataset, it batches it into items to send to the worker,
     // after shuffling. Think of a worker as a seperate process/thread to run the model training on
@@ -127,7 +137,7 @@ pub fn train<B: AutodiffBackend>(
     };
 
     let learner = learner.build(
-        Model::new(&device),
+        Model::new(&device, labels.outputs()),
         config.optimizer.init(),
         config.learning_rate,
     );
@@ -135,11 +145,14 @@ pub fn train<B: AutodiffBackend>(
     // Train the model, and output the fully trained model
     let model_trained = learner.fit(dataloader_train, dataloader_validation);
 
-    // Setup full precision bin file recorder, to write the trained model to.
-    let recorder = BinFileRecorder::<PrecisionSetting>::new();
+    // Describe what the model expects, so a mismatched model is rejected when it is loaded
+    let data_hash = hash_data([train_input, validation_input].concat().as_bytes());
+    let header = ModelHeader::new(labels, config.normalization, data_hash);
 
-    // Write the model using that recorder, and save results in the artifact_dir/model
-    model_trained
-        .save_file(format!("{artifact_dir}/model"), &recorder)
+    // Write the model wrapped in its header to artifact_dir/model.bin
+    let bytes = model_trained
+        .to_bytes(&header)
+        .expect("Trained model should be encoded successfully");
+    std::fs::write(format!("{artifact_dir}/model.bin"), bytes)
         .expect("Trained model should be saved successfully");
 }
@@ -1,17 +1,16 @@
-use burn::module::Module;
-use burn::record::Recorder;
-use burn::record::{BinBytesRecorder, BinFileRecorder, FullPrecisionSettings};
-use burn::tensor::activation::softmax;
-use devil_ml_model::Model;
-use burn::prelude::Backend;
 use burn::data::dataloader::Dataset;
+use burn::prelude::Backend;
+use burn::tensor::activation::softmax;
 use burn::tensor::Tensor;
 use burn::{
     backend::{Autodiff, Wgpu},
     optim::AdamConfig,
 };
+use devil_ml_model::Model;
+use devil_ml_model::SmoothingConfig;
 use devil_ml_model::ARTIFACT_DIR;
 use std::env;
+use training::evaluate::evaluate;
 use training::{data::DevilDataset, train, TrainingConfig};
 
 // Trains the model if `cargo run` is ran and outputs it to /tmp/guide
@@ -26,27 +25,25 @@ fn main() {
 
     println!("ARTIFACT_DIR: {:?}", ARTIFACT_DIR);
 
-    train::<MyAutodiffBackend>(
-        ARTIFACT_DIR,
-        TrainingConfig::new(AdamConfig::new()).with_custom_renderer(false),
-        device.clone(),
-    );
+    let config = TrainingConfig::new(AdamConfig::new()).with_custom_renderer(false);
+    let normalization = config.normalization;
 
-    let data = std::fs::read(format!("{}/model.bin", ARTIFACT_DIR)).unwrap();
+    train::<MyAutodiffBackend>(ARTIFACT_DIR, config, device.clone());
 
-    let record = BinBytesRecorder::<FullPrecisionSettings>::default()
-        .load(data, &device)
-        .expect("failed to find model");
+    let data = std::fs::read(format!("{}/model.bin", ARTIFACT_DIR)).unwrap();
 
-    let model = Model::new(&device).load_record(record);
+    let (model, header) =
+        Model::<MyBackend>::from_embedded(&device, &data).expect("failed to load model");
+    println!("Model trained on data {:016x}", header.data_hash);
 
     let test_input = include_str!(concat!(env!("OUT_DIR"), "/data/testing.csv"));
 
     // Instantiate the dataset from the text
-    let testing_dataset = DevilDataset::new(test_input);
+    let testing_dataset = DevilDataset::new(test_input, &normalization);
 
     testing_dataset.iter().for_each(|item| {
-        let input_to_validate: burn::tensor::Tensor<MyBackend, 1> = Tensor::from_data(item.inputs.as_slice(), &device);
+        let input_to_validate: burn::tensor::Tensor<MyBackend, 1> =
+            Tensor::from_data(item.inputs.as_slice(), &device);
         let inference = devil_ml_model::infer(input_to_validate, &model);
         let inference = softmax(inference, 0);
 
@@ -58,17 +55,40 @@ fn main() {
             .into_iter()
             .enumerate() // Add index onto the probability
             .map(|(index, probability)| {
-                let output = devil_ml_model::Output::try_from(index); // the index is which output it is corresponding with
-                let (output, probability) = match output {
-                    Ok(output) => (output, *probability), // Returns the output gesture and the probability
-                    Err(_) => (devil_ml_model::Output::Unknown, *probability), // This should theoretically never happen, but it's good to test
-                };
-                println!("{:?}: {:?}", output, probability); // Log the results
-                (output, probability) // return the results
+                let output = header.labels.label(index); // the index is which label it is corresponding with
+                println!("{}: {:?}", output, probability); // Log the results
+                (output, *probability) // return the results
             })
             .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap()) // get the gesture with the highest probability
             .unwrap();
         println!("Actual gesture: {:?}", item.label);
         println!("Predicted gesture: {:?}\n\n\n", result.0); // Log the gesture
     });
+
+    // Measure the effect of the smoothing on a whole recording, in the order it was recorded. The testing data is
+    // shuffled, so the original recording is used. Its frames come from disjoint windows.
+    let recording = DevilDataset::new(
+        include_str!("../data/savannah's arm v4.csv"),
+        &normalization,
+    );
+    let frame_period_ms = devil_dsp::WINDOW_LEN as u64 * 1000 / devil_dsp::SAMPLE_RATE_HZ;
+    let evaluation = evaluate(
+        &model,
+        &header.labels,
+        recording.iter(),
+        SmoothingConfig::default(),
+        frame_period_ms,
+        &device,
+    );
+    println!(
+        "Raw: {:.1}% correct, {} changes",
+        evaluation.raw_accuracy() * 100.0,
+        evaluation.raw_changes
+    );
+    println!(
+        "Smoothed: {:.1}% correct, {} changes, {} frames undecided",
+        evaluation.smoothed_accuracy() * 100.0,
+        evaluation.smoothed_changes,
+        evaluation.undecided
+    );
 }
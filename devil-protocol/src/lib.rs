@@ -0,0 +1,491 @@
+// No Standard library is required to run this code, this is so that it can run on the micro controller.
+#![no_std]
+
+// The binary protocol spoken over USB between devil-embedded and devil-trainer. It is shared between the two so
+// the messages can never disagree.
+//
+// Every message is a frame: the protocol VERSION, the message serialized with postcard, and a CRC-32 of both,
+// all COBS encoded so that the frame has no zero bytes in it, followed by a single zero byte. A receiver that
+// starts listening halfway through a frame, or loses some bytes, just drops what it has at the next zero and
+// carries on with the next frame.
+
+extern crate alloc;
+
+pub mod script;
+
+pub use script::{Script, Step};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use serde::{Deserialize, Serialize};
+
+/// Bump this whenever a message changes, frames from another version are rejected
+pub const VERSION: u8 = 4;
+
+/// The longest a frame can be once encoded, including the zero at the end
+pub const MAX_FRAME_LEN: usize = 1024;
+
+// Everything in a frame before it is COBS encoded has to fit in here, COBS adds a byte every 254 and the
+// zero on the end
+const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - MAX_FRAME_LEN / 254 - 2;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// The streams the device can send, each one can be turned on and off by the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stream {
+    /// Every frame of features, before normalization, what the model is trained on
+    Features,
+    /// The samples read from the sensors, before any processing
+    Samples,
+    /// What the model predicted for every frame
+    Predictions,
+}
+
+/// Sent from the host to the device
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    /// Ask for a Status
+    Ping,
+    /// Turn a stream on or off
+    Stream { stream: Stream, enabled: bool },
+    /// Run one of the text commands of devil-embedded (send `help` for the list), it is answered with a
+    /// Message::Reply with the same id
+    Run { id: u16, line: String },
+    /// Record the next frames of features as label, they are sent as Message::Recorded followed by a
+    /// Message::RecordingDone. The recording number is picked by the host, and is sent back with every frame.
+    /// Only done in stream mode.
+    Record {
+        recording: u32,
+        label: String,
+        frames: u32,
+    },
+    /// Stop the recording in progress
+    StopRecording,
+}
+
+/// How a recording ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    /// Every frame asked for was sent
+    Complete,
+    /// It was stopped, or the mode changed, before every frame was sent
+    Cancelled,
+    /// The device wasn't in stream mode, nothing was recorded
+    Refused,
+}
+
+/// Whether a command worked, and if not why
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Code {
+    Ok,
+    /// There is no command with that name
+    UnknownCommand,
+    /// The arguments are missing or can't be parsed
+    BadArgs,
+    /// What the command needs hasn't been set up yet
+    NotReady,
+    /// The command can't be run right now, for example in the current mode
+    Busy,
+    /// The command was understood but didn't work
+    Failed,
+}
+
+/// What the device is doing, see devil-embedded/src/mode.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    /// Nothing is streamed and the servos are off
+    Idle,
+    /// The streams are sent for recording, but nothing is predicted and the servos are off
+    Stream,
+    /// The sensors are being calibrated for the wearer
+    Calibrate,
+    /// The servos are only moved by commands, for fitting the arm
+    Manual,
+    /// The servos follow the predictions of the model
+    Autonomous,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 5] = [
+        Self::Idle,
+        Self::Stream,
+        Self::Calibrate,
+        Self::Manual,
+        Self::Autonomous,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Stream => "stream",
+            Self::Calibrate => "calibrate",
+            Self::Manual => "manual",
+            Self::Autonomous => "autonomous",
+        }
+    }
+
+    /// The mode with the given name, see name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+/// How the device is doing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    /// The version of devil-embedded
+    pub firmware: String,
+    /// The feature pipeline of the firmware, see devil_dsp::PIPELINE_ID
+    pub pipeline: u32,
+    /// The window function the features are computed with, see devil_dsp::WindowFunction::name
+    pub window: String,
+    pub uptime_ms: u64,
+    pub mode: Mode,
+    /// The sample rate actually achieved, in Hz
+    pub sample_rate: u32,
+    /// The frames dropped because the firmware didn't keep up with the sensors
+    pub overruns: u32,
+    pub calibrated: bool,
+    /// Whether every sensor is giving a usable signal
+    pub signal_ok: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    /// A frame of features, the sequence counts up by one with every frame the device computes, so a gap means
+    /// frames were dropped on the way
+    Features {
+        sequence: u32,
+        features: Vec<f32>,
+    },
+    /// Samples from every sensor, one sensor after another for each sample
+    Samples {
+        sequence: u32,
+        channels: u8,
+        samples: Vec<u16>,
+    },
+    /// The prediction made from the frame of features with the same sequence
+    Prediction {
+        sequence: u32,
+        label: String,
+        confidence: f32,
+        probabilities: Vec<f32>,
+    },
+    Status(Status),
+    Command(Command),
+    /// The answer to a Command::Run
+    Reply {
+        id: u16,
+        code: Code,
+        message: String,
+    },
+    /// A frame of features recorded for a Command::Record. index counts the frames of the recording from 0 with
+    /// no gaps, sequence is the same as for Message::Features.
+    Recorded {
+        recording: u32,
+        label: String,
+        index: u32,
+        sequence: u32,
+        features: Vec<f32>,
+    },
+    /// The end of a recording. dropped is the number of frames the device computed during the recording but
+    /// couldn't send, the recorded frames are still exactly the ones asked for.
+    RecordingDone {
+        recording: u32,
+        recorded: u32,
+        dropped: u32,
+        outcome: Outcome,
+    },
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The message doesn't fit in a frame
+    TooLong,
+    Encode(postcard::Error),
+    /// The frame isn't valid COBS, or is too short to hold a version and crc
+    Malformed,
+    /// The frame was damaged on the way
+    BadCrc,
+    /// The frame is from another version of the protocol
+    UnsupportedVersion(u8),
+    Decode(postcard::Error),
+}
+
+/// Encode a message into a frame, returns the length of the frame
+pub fn encode(message: &Message, frame: &mut [u8]) -> Result<usize, Error> {
+    let mut payload = [0u8; MAX_PAYLOAD_LEN];
+    payload[0] = VERSION;
+    let len = 1 + postcard::to_slice(message, &mut payload[1..MAX_PAYLOAD_LEN - 4])
+        .map_err(|err| match err {
+            postcard::Error::SerializeBufferFull => Error::TooLong,
+            err => Error::Encode(err),
+        })?
+        .len();
+
+    let crc = CRC.checksum(&payload[..len]);
+    payload[len..len + 4].copy_from_slice(&crc.to_le_bytes());
+    let len = len + 4;
+
+    if frame.len() < cobs::max_encoding_length(len) + 1 {
+        return Err(Error::TooLong);
+    }
+    let encoded = cobs::encode(&payload[..len], frame);
+    frame[encoded] = 0;
+    Ok(encoded + 1)
+}
+
+/// Decode a single frame, without the zero on the end. The frame is decoded in place.
+pub fn decode(frame: &mut [u8]) -> Result<Message, Error> {
+    let len = cobs::decode_in_place(frame).map_err(|_| Error::Malformed)?;
+    if len < 5 {
+        return Err(Error::Malformed);
+    }
+
+    let (payload, crc) = frame[..len].split_at(len - 4);
+    if CRC.checksum(payload).to_le_bytes() != crc {
+        return Err(Error::BadCrc);
+    }
+    if payload[0] != VERSION {
+        return Err(Error::UnsupportedVersion(payload[0]));
+    }
+
+    postcard::from_bytes(&payload[1..]).map_err(Error::Decode)
+}
+
+/// Splits a stream of bytes into frames, and decodes them
+#[derive(Default)]
+pub struct Decoder {
+    frame: Vec<u8>,
+    // Set when the frame got too long, everything up to the next zero is thrown away
+    overflowed: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed in the next byte, returns a message (or why it couldn't be decoded) at the end of every frame
+    pub fn push(&mut self, byte: u8) -> Option<Result<Message, Error>> {
+        if byte != 0 {
+            if self.frame.len() < MAX_FRAME_LEN {
+                self.frame.push(byte);
+            } else {
+                self.overflowed = true;
+            }
+            return None;
+        }
+
+        let result = match (self.frame.is_empty(), self.overflowed) {
+            // Zeros between frames are fine, there is nothing to decode
+            (true, false) => None,
+            (_, true) => Some(Err(Error::TooLong)),
+            (false, false) => Some(decode(&mut self.frame)),
+        };
+        self.frame.clear();
+        self.overflowed = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn round_trip(message: Message) {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let len = encode(&message, &mut frame).unwrap();
+        assert_eq!(frame[len - 1], 0);
+        assert!(
+            !frame[..len - 1].contains(&0),
+            "{:?} has a zero inside its frame",
+            message
+        );
+        assert_eq!(decode(&mut frame[..len - 1]).unwrap(), message);
+    }
+
+    // COBS encode a payload of our own, the version and crc included, without the zero on the end
+    fn frame_of(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; cobs::max_encoding_length(payload.len())];
+        let len = cobs::encode(payload, &mut frame);
+        frame.truncate(len);
+        frame
+    }
+
+    fn with_crc(mut payload: Vec<u8>) -> Vec<u8> {
+        let crc = CRC.checksum(&payload);
+        payload.extend_from_slice(&crc.to_le_bytes());
+        payload
+    }
+
+    fn ping() -> Vec<u8> {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let len = encode(&Message::Command(Command::Ping), &mut frame).unwrap();
+        frame[..len].to_vec()
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        round_trip(Message::Features {
+            sequence: 7,
+            features: vec![0.0, -1.5, 1000.25],
+        });
+        round_trip(Message::Samples {
+            sequence: 8,
+            channels: 3,
+            samples: vec![0, 512, u16::MAX, 1, 2, 3],
+        });
+        round_trip(Message::Prediction {
+            sequence: 9,
+            label: "fist".to_string(),
+            confidence: 0.75,
+            probabilities: vec![0.75, 0.25],
+        });
+        round_trip(Message::Status(Status {
+            firmware: "0.1.0".to_string(),
+            pipeline: 0xdead_beef,
+            window: "hann".to_string(),
+            uptime_ms: u64::MAX,
+            mode: Mode::Autonomous,
+            sample_rate: 1000,
+            overruns: 2,
+            calibrated: true,
+            signal_ok: false,
+        }));
+        round_trip(Message::Reply {
+            id: 3,
+            code: Code::BadArgs,
+            message: "expected a number".to_string(),
+        });
+        round_trip(Message::Recorded {
+            recording: 1,
+            label: "open".to_string(),
+            index: 0,
+            sequence: 10,
+            features: vec![1.0, 2.0],
+        });
+        round_trip(Message::RecordingDone {
+            recording: 1,
+            recorded: 200,
+            dropped: 4,
+            outcome: Outcome::Cancelled,
+        });
+    }
+
+    #[test]
+    fn every_command_round_trips() {
+        let commands = [
+            Command::Ping,
+            Command::Stream {
+                stream: Stream::Predictions,
+                enabled: true,
+            },
+            Command::Run {
+                id: u16::MAX,
+                line: "mode stream".to_string(),
+            },
+            Command::Record {
+                recording: 2,
+                label: "point".to_string(),
+                frames: 200,
+            },
+            Command::StopRecording,
+        ];
+        for command in commands {
+            round_trip(Message::Command(command));
+        }
+    }
+
+    #[test]
+    fn corrupted_crc_is_rejected() {
+        let message = postcard::to_allocvec(&Message::Command(Command::Ping)).unwrap();
+        let mut payload = with_crc([&[VERSION][..], &message].concat());
+        assert_eq!(
+            decode(&mut frame_of(&payload)).unwrap(),
+            Message::Command(Command::Ping)
+        );
+
+        let last = payload.len() - 1;
+        payload[last] ^= 0x01;
+        assert!(matches!(
+            decode(&mut frame_of(&payload)),
+            Err(Error::BadCrc)
+        ));
+    }
+
+    #[test]
+    fn another_version_is_rejected() {
+        let message = postcard::to_allocvec(&Message::Command(Command::Ping)).unwrap();
+        let payload = with_crc([&[VERSION + 1][..], &message].concat());
+        assert!(matches!(
+            decode(&mut frame_of(&payload)),
+            Err(Error::UnsupportedVersion(version)) if version == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn decoder_resyncs_after_garbage() {
+        let mut decoder = Decoder::new();
+        // Zeros between frames are skipped, the garbage is decoded and rejected at the next zero
+        assert!(decoder.push(0).is_none());
+        let results: Vec<_> = [1, 2, 3, 0]
+            .into_iter()
+            .filter_map(|byte| decoder.push(byte))
+            .collect();
+        assert!(matches!(results[..], [Err(_)]));
+
+        let results: Vec<_> = ping()
+            .into_iter()
+            .filter_map(|byte| decoder.push(byte))
+            .collect();
+        assert!(matches!(
+            &results[..],
+            [Ok(Message::Command(Command::Ping))]
+        ));
+    }
+
+    #[test]
+    fn decoder_resyncs_after_an_oversized_frame() {
+        let mut decoder = Decoder::new();
+        for _ in 0..MAX_FRAME_LEN * 2 {
+            assert!(decoder.push(0xff).is_none());
+        }
+        assert!(matches!(decoder.push(0), Some(Err(Error::TooLong))));
+
+        let results: Vec<_> = ping()
+            .into_iter()
+            .filter_map(|byte| decoder.push(byte))
+            .collect();
+        assert!(matches!(
+            &results[..],
+            [Ok(Message::Command(Command::Ping))]
+        ));
+    }
+
+    #[test]
+    fn largest_features_frame_fits() {
+        // The largest sequence and a feature that can't be shrunk, every f32 is four bytes whatever its value
+        round_trip(Message::Features {
+            sequence: u32::MAX,
+            features: vec![f32::MAX; devil_dsp::FRAME_LEN],
+        });
+    }
+
+    #[test]
+    fn too_long_a_message_is_refused() {
+        let message = Message::Features {
+            sequence: 0,
+            features: vec![1.0; MAX_FRAME_LEN / 4],
+        };
+        assert!(matches!(
+            encode(&message, &mut [0u8; MAX_FRAME_LEN]),
+            Err(Error::TooLong)
+        ));
+    }
+}
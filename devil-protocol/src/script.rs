@@ -0,0 +1,214 @@
+// A recording script, which lays out a data collection session so it can be run the same way every time. It is
+// plain text, one step on each line:
+//
+// # Anything after a # is a comment
+// prompt Relax your arm
+// rest 3000
+// record 200 unknown
+// prompt Make a fist
+// record 200 fist
+//
+// prompt shows the text to the wearer, rest waits for a number of milliseconds without recording, and record
+// records a number of frames with the label, which is everything after the number of frames.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    Prompt(String),
+    Rest { ms: u32 },
+    Record { frames: u32, label: String },
+}
+
+/// Why a script couldn't be parsed, line counts from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script {
+    pub steps: Vec<Step>,
+    /// A hash of the text of the script, saved with the recording so it is known which script made it
+    pub hash: u32,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut steps = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |reason| ScriptError {
+                line: index + 1,
+                reason,
+            };
+
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (step, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let args = args.trim();
+
+            steps.push(match step {
+                "prompt" => Step::Prompt(String::from(args)),
+                "rest" => Step::Rest {
+                    ms: args
+                        .parse()
+                        .map_err(|_| error("rest needs a number of milliseconds"))?,
+                },
+                "record" => {
+                    let (frames, label) = args
+                        .split_once(char::is_whitespace)
+                        .ok_or(error("record needs a number of frames and a label"))?;
+                    let label = label.trim();
+                    check_label(label).map_err(error)?;
+                    Step::Record {
+                        frames: frames
+                            .parse()
+                            .map_err(|_| error("record needs a number of frames"))?,
+                        label: String::from(label),
+                    }
+                }
+                _ => return Err(error("expected prompt, rest or record")),
+            });
+        }
+
+        Ok(Self {
+            steps,
+            hash: fnv1a(text.as_bytes()),
+        })
+    }
+
+    /// A script which just records frames of label, the label is checked as if it were line 1 of a script
+    pub fn single(label: &str, frames: u32) -> Result<Self, ScriptError> {
+        check_label(label).map_err(|reason| ScriptError { line: 1, reason })?;
+        Ok(Self {
+            steps: alloc::vec![Step::Record {
+                frames,
+                label: String::from(label),
+            }],
+            hash: 0,
+        })
+    }
+}
+
+// The label ends up as the last column of a csv row, so it can't have a comma in it
+fn check_label(label: &str) -> Result<(), &'static str> {
+    if label.contains(',') {
+        return Err("labels can't have commas in them");
+    }
+    Ok(())
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn record(frames: u32, label: &str) -> Step {
+        Step::Record {
+            frames,
+            label: String::from(label),
+        }
+    }
+
+    #[test]
+    fn parses_every_step() {
+        let script = Script::parse("prompt Make a fist\nrest 3000\nrecord 200 fist\n").unwrap();
+        assert_eq!(
+            script.steps,
+            vec![
+                Step::Prompt(String::from("Make a fist")),
+                Step::Rest { ms: 3000 },
+                record(200, "fist")
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_lines_and_comments_are_skipped() {
+        let text = "# A session\n\n   \nrecord 10 open # the hand open\n  # indented comment\nrest 5#no space\n";
+        let script = Script::parse(text).unwrap();
+        assert_eq!(script.steps, vec![record(10, "open"), Step::Rest { ms: 5 }]);
+        assert!(Script::parse("# nothing but comments\n\n")
+            .unwrap()
+            .steps
+            .is_empty());
+    }
+
+    #[test]
+    fn label_is_everything_after_the_frames() {
+        let script = Script::parse("record 50   thumbs up  ").unwrap();
+        assert_eq!(script.steps, vec![record(50, "thumbs up")]);
+    }
+
+    #[test]
+    fn bad_frame_counts_are_refused() {
+        for line in [
+            "record many fist",
+            "record -1 fist",
+            "record 1.5 fist",
+            "record 99999999999 fist",
+        ] {
+            assert_eq!(
+                Script::parse(line),
+                Err(ScriptError {
+                    line: 1,
+                    reason: "record needs a number of frames"
+                }),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            Script::parse("record 200").unwrap_err().reason,
+            "record needs a number of frames and a label"
+        );
+    }
+
+    #[test]
+    fn labels_with_commas_are_refused() {
+        let error = ScriptError {
+            line: 1,
+            reason: "labels can't have commas in them",
+        };
+        assert_eq!(Script::parse("record 200 fist,open"), Err(error.clone()));
+        assert_eq!(Script::single("fist,open", 200), Err(error));
+        assert_eq!(
+            Script::single("fist", 200).unwrap().steps,
+            vec![record(200, "fist")]
+        );
+    }
+
+    #[test]
+    fn errors_give_the_line_they_are_on() {
+        let text = "# comment\n\nprompt Relax\nrest soon\n";
+        assert_eq!(
+            Script::parse(text),
+            Err(ScriptError {
+                line: 4,
+                reason: "rest needs a number of milliseconds"
+            })
+        );
+        assert_eq!(Script::parse("rest 1\nwave 3").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn hash_follows_the_text() {
+        let script = Script::parse("record 10 open").unwrap();
+        assert_eq!(script.hash, Script::parse("record 10 open").unwrap().hash);
+        assert_ne!(
+            script.hash,
+            Script::parse("record 10 open # again").unwrap().hash
+        );
+    }
+}
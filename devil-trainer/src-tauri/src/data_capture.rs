@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use devil_dsp::window::WINDOW_TAG;
+use devil_protocol::{
+    Command, Decoder, Message, Mode, Outcome, Script, Status, Step, Stream, MAX_FRAME_LEN,
+};
+use serialport::SerialPort;
+use tauri::Emitter;
+
+use crate::GLOBAL_APP_HANDLE;
+
+// How long to wait for the arm to send anything before giving up
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long to wait for the arm to send the status asked for, see wait_for_status
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Records frames of features from the arm's data port, the second of its two serial ports (see devil-protocol),
+// into a csv that training can read, every row labelled with label. A new csv starts with the window the features were computed with.
+#[tauri::command]
+pub fn capture_features(
+    port: String,
+    path: String,
+    label: String,
+    frames: u32,
+) -> Result<String, String> {
+    let script = Script::single(&label, frames).map_err(|e| format!("Bad label: {}", e.reason))?;
+    let dropped =
+        record(&port, &path, &script).map_err(|e| format!("Failed to capture: {:#}", e))?;
+    Ok(format!(
+        "Recorded {} frames of {} to {}, {} frames were dropped on the way",
+        frames, label, path, dropped
+    ))
+}
+
+// Runs a recording script (see devil_protocol::script) against the arm, so a session is recorded the same way
+// every time. Prompts are sent to the frontend as recording-prompt events.
+#[tauri::command]
+pub fn record_session(port: String, script: String, path: String) -> Result<String, String> {
+    let text =
+        fs::read_to_string(&script).map_err(|e| format!("Failed to read the script: {}", e))?;
+    let script =
+        Script::parse(&text).map_err(|e| format!("Line {} of the script: {}", e.line, e.reason))?;
+    let dropped =
+        record(&port, &path, &script).map_err(|e| format!("Failed to record: {:#}", e))?;
+    Ok(format!(
+        "Recorded {} steps to {}, {} frames were dropped on the way",
+        script.steps.len(),
+        path,
+        dropped
+    ))
+}
+
+// Returns how many frames the arm dropped during the recordings. The labels are put on by the arm, so every
+// row has exactly the label it was recorded for.
+fn record(port: &str, path: &str, script: &Script) -> anyhow::Result<u32> {
+    let port = serialport::new(port, 115_200)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .context("Failed to open the serial port")?;
+    let mut reader = Reader::new(port);
+
+    // The mode the arm was in, so it can be put back afterwards
+    send(reader.port(), Command::Ping)?;
+    let previous = wait_for_status(&mut reader, |_| true)
+        .context("The arm didn't send its status")?
+        .mode;
+
+    // Put the arm in stream mode so it holds still while recording, the recorded frames come separately from
+    // the features stream so it is turned off, and ask for a status straight away for the window
+    send(
+        reader.port(),
+        Command::Run {
+            id: 0,
+            line: "mode stream".to_string(),
+        },
+    )?;
+    send(
+        reader.port(),
+        Command::Stream {
+            stream: Stream::Features,
+            enabled: false,
+        },
+    )?;
+    send(reader.port(), Command::Ping)?;
+
+    // However the recording ends the arm is put back the way it was, a failed recording is stopped first so
+    // the arm doesn't carry on with it
+    let result = record_script(&mut reader, path, script);
+    if result.is_err() {
+        let _ = send(reader.port(), Command::StopRecording);
+    }
+    let restored = restore(reader.port(), previous);
+    let dropped = result?;
+    restored.context("Failed to put the arm back the way it was")?;
+    Ok(dropped)
+}
+
+// Turns the features stream back on, and puts the arm back in the mode it was in before recording
+fn restore(port: &mut impl Write, previous: Mode) -> anyhow::Result<()> {
+    send(
+        port,
+        Command::Stream {
+            stream: Stream::Features,
+            enabled: true,
+        },
+    )?;
+    if previous != Mode::Stream {
+        send(
+            port,
+            Command::Run {
+                id: 0,
+                line: format!("mode {}", previous.name()),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+// Waits for a status which matches, other messages are thrown away. The arm keeps sending other messages
+// meanwhile, so READ_TIMEOUT alone would never end the wait.
+fn wait_for_status(
+    reader: &mut Reader,
+    matches: impl Fn(&Status) -> bool,
+) -> anyhow::Result<Status> {
+    let deadline = Instant::now() + STATUS_TIMEOUT;
+    loop {
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "No status within {} seconds",
+                STATUS_TIMEOUT.as_secs()
+            ));
+        }
+        if let Message::Status(status) = reader.next()? {
+            if matches(&status) {
+                return Ok(status);
+            }
+        }
+    }
+}
+
+// Records the steps of the script once the arm has been asked to go into stream mode
+fn record_script(reader: &mut Reader, path: &str, script: &Script) -> anyhow::Result<u32> {
+    let mut csv = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Failed to open the csv")?;
+    let new_csv = csv.metadata()?.len() == 0;
+
+    // The window is only known once the arm has sent its status, so nothing is recorded before that. A status
+    // from before the arm changed mode is no good, it would refuse to record.
+    let status = wait_for_status(reader, |status| status.mode == Mode::Stream)
+        .context("The arm didn't switch to stream mode")?;
+    if new_csv {
+        writeln!(csv, "{}{}", WINDOW_TAG, status.window)?;
+    }
+    // Enough to tell exactly how the rows that follow were made
+    writeln!(
+        csv,
+        "# script {:08x}, firmware {}, pipeline {:08x}, window {}",
+        script.hash, status.firmware, status.pipeline, status.window
+    )?;
+
+    let mut dropped = 0;
+    for (number, step) in script.steps.iter().enumerate() {
+        match step {
+            Step::Prompt(text) => {
+                println!("{}", text);
+                if let Some(app) = GLOBAL_APP_HANDLE.get() {
+                    app.emit("recording-prompt", text)?;
+                }
+            }
+            // Keep reading so the arm isn't left waiting on a full port, nothing sent while resting is kept
+            Step::Rest { ms } => {
+                let end = Instant::now() + Duration::from_millis((*ms).into());
+                while Instant::now() < end {
+                    reader.poll()?;
+                    reader.messages.clear();
+                }
+            }
+            Step::Record { frames, label } => {
+                let recording = number as u32;
+                send(
+                    reader.port(),
+                    Command::Record {
+                        recording,
+                        label: label.clone(),
+                        frames: *frames,
+                    },
+                )?;
+                dropped += record_step(reader, &mut csv, recording, *frames)?;
+            }
+        }
+    }
+
+    Ok(dropped)
+}
+
+// Writes the frames of one recording to the csv, returns how many frames the arm dropped
+fn record_step(
+    reader: &mut Reader,
+    csv: &mut impl Write,
+    recording: u32,
+    frames: u32,
+) -> anyhow::Result<u32> {
+    let mut next_index = 0;
+
+    loop {
+        match reader.next()? {
+            Message::Recorded {
+                recording: from,
+                label,
+                index,
+                features,
+                ..
+            } if from == recording => {
+                if index != next_index {
+                    return Err(anyhow!(
+                        "Frame {} of recording {} went missing",
+                        next_index,
+                        recording
+                    ));
+                }
+                next_index += 1;
+
+                let row: Vec<String> = features.iter().map(|feature| feature.to_string()).collect();
+                writeln!(csv, "{},{}", row.join(","), label)?;
+            }
+            Message::RecordingDone {
+                recording: from,
+                recorded,
+                dropped,
+                outcome,
+            } if from == recording => {
+                return match outcome {
+                    Outcome::Complete if recorded == frames && next_index == frames => Ok(dropped),
+                    Outcome::Complete => Err(anyhow!(
+                        "Recording {} ended with {} of {} frames",
+                        recording,
+                        next_index,
+                        frames
+                    )),
+                    Outcome::Cancelled => {
+                        Err(anyhow!("Recording {} was cancelled on the arm", recording))
+                    }
+                    Outcome::Refused => Err(anyhow!(
+                        "The arm refused to record, it isn't in stream mode"
+                    )),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+// Reads messages from the data port
+struct Reader {
+    port: Box<dyn SerialPort>,
+    decoder: Decoder,
+    messages: VecDeque<Message>,
+    last_data: Instant,
+}
+
+impl Reader {
+    fn new(port: Box<dyn SerialPort>) -> Self {
+        Self {
+            port,
+            decoder: Decoder::new(),
+            messages: VecDeque::new(),
+            last_data: Instant::now(),
+        }
+    }
+
+    fn port(&mut self) -> &mut Box<dyn SerialPort> {
+        &mut self.port
+    }
+
+    // Waits for the next message
+    fn next(&mut self) -> anyhow::Result<Message> {
+        loop {
+            if let Some(message) = self.messages.pop_front() {
+                return Ok(message);
+            }
+            self.poll()?;
+        }
+    }
+
+    // Reads whatever has arrived, and decodes it
+    fn poll(&mut self) -> anyhow::Result<()> {
+        let mut buf = [0u8; 256];
+        let len = match self.port.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if len > 0 {
+            self.last_data = Instant::now();
+        } else if self.last_data.elapsed() > READ_TIMEOUT {
+            return Err(anyhow!("The arm stopped sending data"));
+        }
+
+        for &byte in &buf[..len] {
+            match self.decoder.push(byte) {
+                Some(Ok(message)) => self.messages.push_back(message),
+                Some(Err(err)) => eprintln!("Dropped a frame: {:?}", err),
+                None => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn send(port: &mut impl Write, command: Command) -> anyhow::Result<()> {
+    let mut frame = [0u8; MAX_FRAME_LEN];
+    let len = devil_protocol::encode(&Message::Command(command), &mut frame)
+        .map_err(|err| anyhow!("Failed to encode a command: {:?}", err))?;
+    port.write_all(&frame[..len])?;
+    port.flush()?;
+    Ok(())
+}
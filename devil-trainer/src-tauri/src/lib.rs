@@ -2,6 +2,9 @@ use once_cell::sync::OnceCell;
 use std::process::Command;
 use tauri::AppHandle;
 
+mod data_capture;
+mod model_upload;
+
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 
 // Runs the DevilArm project, will eventually just take a path as input
@@ -49,7 +52,10 @@ pub fn run() {
         })
         .plugin(tauri_plugin_shell::init())
         .invoke_handler(tauri::generate_handler![
-            upload_file_to_pi
+            upload_file_to_pi,
+            model_upload::upload_model,
+            data_capture::capture_features,
+            data_capture::record_session
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,95 @@
+use std::io::{BufRead, BufReader, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context};
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+// The crc the arm checks the model with, see devil-embedded/src/model_partition.rs
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+// The number of bytes of the model sent in each chunk. Every command has to fit in a single 64 byte USB packet,
+// and each byte takes two characters as hex.
+const CHUNK_LEN: usize = 16;
+
+// How long to wait for the arm to reply to a command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Uploads a trained model (the model.bin in the artifact directory) to the arm over its serial port, so it
+// can be used without reflashing the firmware. The arm has to be rebooted afterwards to load it.
+#[tauri::command]
+pub fn upload_model(port: String, path: String) -> Result<String, String> {
+    let model = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    upload(&port, &model).map_err(|e| format!("Failed to upload the model: {:#}", e))?;
+    Ok(format!(
+        "Uploaded {} bytes to {}, reboot the arm to use it",
+        model.len(),
+        port
+    ))
+}
+
+fn upload(port: &str, model: &[u8]) -> anyhow::Result<()> {
+    let mut port = serialport::new(port, 115_200)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .context("Failed to open the serial port")?;
+    let mut reader = BufReader::new(
+        port.try_clone()
+            .context("Failed to clone the serial port")?,
+    );
+
+    let mut send = |command: String| -> anyhow::Result<()> {
+        port.write_all(command.as_bytes())?;
+        port.flush()?;
+        Ok(())
+    };
+
+    // Erasing the partition can take a moment, so wait for it to be acknowledged before sending anything else
+    send(format!(
+        "model begin {} {:08x}",
+        model.len(),
+        CRC.checksum(model)
+    ))?;
+    wait_for(&mut reader, "model ack 0")?;
+
+    for (index, chunk) in model.chunks(CHUNK_LEN).enumerate() {
+        let offset = index * CHUNK_LEN;
+        let hex: String = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        send(format!("model data {} {}", offset, hex))?;
+        wait_for(&mut reader, &format!("model ack {}", offset + chunk.len()))?;
+    }
+
+    send("model end".to_string())?;
+    wait_for(&mut reader, "model done")?;
+
+    Ok(())
+}
+
+// Read lines from the arm until one is exactly expected. The arm logs other things at the same time, those are
+// skipped, but a line about the model failing ends the upload.
+fn wait_for(reader: &mut impl BufRead, expected: &str) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let mut line = String::new();
+
+    while start.elapsed() < REPLY_TIMEOUT {
+        // A read that times out keeps what it got in line, so a reply split across reads is put back together
+        match reader.read_line(&mut line) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        }
+        if !line.ends_with('\n') {
+            continue;
+        }
+
+        let reply = line.trim();
+        if reply == expected {
+            return Ok(());
+        }
+        if reply.starts_with("model failed") {
+            bail!("{}", reply);
+        }
+        line.clear();
+    }
+
+    Err(anyhow!("Timed out waiting for \"{}\"", expected))
+}